@@ -0,0 +1,334 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{error::TrieError, single_threaded_trie::Trie};
+
+/// A `Trie` with a bounded size.
+///
+/// Made with `new`, it holds at most `capacity` keys and inserting past the capacity evicts the
+/// least recently accessed key. Made with `with_node_cap`, it holds at most `max_nodes` nodes
+/// and inserting past that evicts every key starting with the least accessed first char. Both
+/// `insert` and `get` count as an access.
+///
+/// Keys with the same first char are only evicted together, so with a node cap `insert` returns
+/// `TrieError::NodeCapExceeded` for a key whose first char's subtree would not fit in
+/// `max_nodes` by itself.
+pub struct BoundedTrie<T> {
+    trie: Trie<T>,
+    eviction: Eviction,
+}
+
+enum Eviction {
+    LeastRecentKey {
+        capacity: usize,
+        // Last access tick of every stored key, and the stored keys ordered by it
+        last_access: HashMap<String, u64>,
+        recency: BTreeMap<u64, String>,
+        tick: u64,
+    },
+    ColdestSubtree {
+        max_nodes: usize,
+        node_count: usize,
+        // Statistics of the subtree below every first char
+        subtrees: HashMap<char, SubtreeStats>,
+    },
+}
+
+#[derive(Default)]
+struct SubtreeStats {
+    node_count: usize,
+    accesses: u64,
+}
+
+impl<T> BoundedTrie<T> {
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be greater than zero");
+        Self {
+            trie: Trie::new(),
+            eviction: Eviction::LeastRecentKey {
+                capacity,
+                last_access: HashMap::new(),
+                recency: BTreeMap::new(),
+                tick: 0,
+            },
+        }
+    }
+
+    /// Creates a trie bounded by its number of nodes instead of keys, so that memory stays
+    /// bounded however long the keys are.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_nodes` is zero.
+    pub fn with_node_cap(max_nodes: usize) -> Self {
+        assert!(max_nodes > 0, "max_nodes must be greater than zero");
+        Self {
+            trie: Trie::new(),
+            eviction: Eviction::ColdestSubtree {
+                max_nodes,
+                node_count: 0,
+                subtrees: HashMap::new(),
+            },
+        }
+    }
+
+    /// Returns the maximum number of keys, which is `usize::MAX` when bounded by nodes
+    pub fn capacity(&self) -> usize {
+        match self.eviction {
+            Eviction::LeastRecentKey { capacity, .. } => capacity,
+            Eviction::ColdestSubtree { .. } => usize::MAX,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.trie.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trie.is_empty()
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<&T> {
+        self.trie.get(key)?;
+        self.touch(key);
+        self.trie.get(key)
+    }
+
+    pub fn insert(&mut self, key: &str, value: T) -> Result<(), TrieError> {
+        let added_nodes = match &self.eviction {
+            Eviction::LeastRecentKey { .. } => 0,
+            Eviction::ColdestSubtree {
+                max_nodes,
+                subtrees,
+                ..
+            } => {
+                let added_nodes = key.chars().count() - self.trie.longest_shared_path(key, key);
+                // Evicting the other subtrees must be enough to get back under the cap
+                let subtree_nodes = key
+                    .chars()
+                    .next()
+                    .and_then(|first_key| subtrees.get(&first_key))
+                    .map_or(0, |stats| stats.node_count);
+                if subtree_nodes + added_nodes > *max_nodes {
+                    return Err(TrieError::NodeCapExceeded);
+                }
+                added_nodes
+            }
+        };
+        self.trie.insert(key, value)?;
+
+        if let Eviction::ColdestSubtree {
+            node_count,
+            subtrees,
+            ..
+        } = &mut self.eviction
+        {
+            let first_key = key.chars().next().unwrap();
+            subtrees.entry(first_key).or_default().node_count += added_nodes;
+            *node_count += added_nodes;
+        }
+        self.touch(key);
+        self.evict(key)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Result<(), TrieError> {
+        match &mut self.eviction {
+            Eviction::LeastRecentKey {
+                last_access,
+                recency,
+                ..
+            } => {
+                self.trie.remove(key)?;
+                let tick = last_access.remove(key).unwrap();
+                recency.remove(&tick);
+            }
+            Eviction::ColdestSubtree {
+                node_count,
+                subtrees,
+                ..
+            } => {
+                let pruned_nodes = self.trie.remove_report(key)?.pruned_nodes;
+                let first_key = key.chars().next().unwrap();
+                let stats = subtrees.get_mut(&first_key).unwrap();
+                stats.node_count -= pruned_nodes;
+                if stats.node_count == 0 {
+                    subtrees.remove(&first_key);
+                }
+                *node_count -= pruned_nodes;
+            }
+        }
+        Ok(())
+    }
+
+    // Evicts keys until the trie is within its bound again, keeping `inserted_key`
+    fn evict(&mut self, inserted_key: &str) -> Result<(), TrieError> {
+        match &mut self.eviction {
+            Eviction::LeastRecentKey {
+                capacity,
+                last_access,
+                recency,
+                ..
+            } => {
+                if last_access.len() > *capacity {
+                    let (_, oldest_key) = recency.pop_first().unwrap();
+                    last_access.remove(&oldest_key);
+                    self.trie.remove(&oldest_key)?;
+                }
+            }
+            Eviction::ColdestSubtree {
+                max_nodes,
+                node_count,
+                subtrees,
+            } => {
+                let inserted_first_key = inserted_key.chars().next().unwrap();
+                while *node_count > *max_nodes {
+                    // `insert` made sure the subtree of `inserted_key` fits by itself
+                    let coldest_key = subtrees
+                        .iter()
+                        .filter(|(key, _)| **key != inserted_first_key)
+                        .min_by_key(|(key, stats)| (stats.accesses, **key))
+                        .map(|(key, _)| *key)
+                        .unwrap();
+                    *node_count -= subtrees.remove(&coldest_key).unwrap().node_count;
+                    self.trie.remove_prefix(&coldest_key.to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Records an access of `key`, which is stored
+    fn touch(&mut self, key: &str) {
+        match &mut self.eviction {
+            Eviction::LeastRecentKey {
+                last_access,
+                recency,
+                tick,
+                ..
+            } => {
+                // Marks `key` as the most recently accessed key
+                *tick += 1;
+                match last_access.get_mut(key) {
+                    Some(last_tick) => {
+                        let key = recency.remove(last_tick).unwrap();
+                        *last_tick = *tick;
+                        recency.insert(*tick, key);
+                    }
+                    None => {
+                        last_access.insert(key.to_owned(), *tick);
+                        recency.insert(*tick, key.to_owned());
+                    }
+                }
+            }
+            Eviction::ColdestSubtree { subtrees, .. } => {
+                let first_key = key.chars().next().unwrap();
+                subtrees.get_mut(&first_key).unwrap().accesses += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_accessed_key() {
+        // Create new `BoundedTrie`
+        let mut trie = BoundedTrie::new(2);
+
+        // Insert values
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("hey", 2).is_ok());
+
+        // Access `hello` so `hey` becomes the oldest key
+        assert_eq!(trie.get("hello"), Some(&1));
+
+        // Insert past capacity
+        assert!(trie.insert("back", 3).is_ok());
+        assert_eq!(trie.len(), 2);
+
+        // Get values
+        assert_eq!(trie.get("hey"), None);
+        assert_eq!(trie.get("hello"), Some(&1));
+        assert_eq!(trie.get("back"), Some(&3));
+
+        // `hello` was accessed before `back`
+        assert!(trie.insert("bag", 4).is_ok());
+        assert_eq!(trie.get("hello"), None);
+        assert_eq!(trie.get("back"), Some(&3));
+    }
+
+    #[test]
+    fn remove_frees_capacity() {
+        // Create new `BoundedTrie`
+        let mut trie = BoundedTrie::new(2);
+
+        // Insert values
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("hey", 2).is_ok());
+        assert!(trie.insert("hey", 3).is_err());
+
+        // Delete value
+        assert!(trie.remove("hello").is_ok());
+        assert!(trie.remove("hello").is_err());
+
+        // Nothing needs to be evicted
+        assert!(trie.insert("back", 3).is_ok());
+        assert_eq!(trie.get("hey"), Some(&2));
+        assert_eq!(trie.get("back"), Some(&3));
+    }
+
+    #[test]
+    fn node_cap_evicts_coldest_subtree() {
+        // Create new `BoundedTrie`
+        let mut trie = BoundedTrie::with_node_cap(12);
+
+        // Insert values, 11 nodes in total
+        assert!(trie.insert("apple", 1).is_ok());
+        assert!(trie.insert("banana", 2).is_ok());
+        assert!(trie.insert("app", 3).is_ok());
+
+        // Access `banana` so the subtree of `a` becomes the coldest one
+        assert_eq!(trie.get("banana"), Some(&2));
+        assert_eq!(trie.get("banana"), Some(&2));
+        assert_eq!(trie.get("banana"), Some(&2));
+
+        // Insert past the node cap
+        assert!(trie.insert("cherry", 4).is_ok());
+        assert_eq!(trie.len(), 2);
+        assert_eq!(trie.get("apple"), None);
+        assert_eq!(trie.get("app"), None);
+        assert_eq!(trie.get("banana"), Some(&2));
+        assert_eq!(trie.get("cherry"), Some(&4));
+
+        // Removing keys frees their nodes
+        assert!(trie.remove("banana").is_ok());
+        assert!(trie.insert("berry", 5).is_ok());
+        assert_eq!(trie.get("cherry"), Some(&4));
+        assert_eq!(trie.capacity(), usize::MAX);
+    }
+
+    #[test]
+    fn node_cap_rejects_keys_that_can_not_fit() {
+        // Create new `BoundedTrie`
+        let mut trie = BoundedTrie::with_node_cap(5);
+        assert!(trie.insert("ab", 1).is_ok());
+        assert!(trie.insert("bc", 2).is_ok());
+
+        // A single key longer than the cap is rejected without evicting anything
+        assert_eq!(trie.insert("cdefgh", 3), Err(TrieError::NodeCapExceeded));
+        assert_eq!(trie.len(), 2);
+        assert_eq!(trie.get("ab"), Some(&1));
+
+        // So is a key whose subtree would outgrow the cap
+        assert!(trie.insert("abc", 4).is_ok());
+        assert_eq!(trie.insert("abcdef", 5), Err(TrieError::NodeCapExceeded));
+        assert!(trie.insert("abcde", 5).is_ok());
+        assert_eq!(trie.len(), 3);
+        assert_eq!(trie.get("bc"), None);
+        assert_eq!(trie.get("abcde"), Some(&5));
+    }
+}
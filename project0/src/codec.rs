@@ -0,0 +1,163 @@
+use std::io::{self, Read, Write};
+
+/// Types that can be written in the crate's binary format, e.g. by `Trie::write_to`.
+///
+/// Lengths are written as varints and integers as fixed width little endian.
+pub trait Encode {
+    fn encode(&self, w: &mut impl Write) -> io::Result<()>;
+}
+
+/// Types that can be read back from the crate's binary format
+pub trait Decode: Sized {
+    fn decode(r: &mut impl Read) -> io::Result<Self>;
+}
+
+/// Writes `value` as an unsigned LEB128 varint
+pub fn write_varint(w: &mut impl Write, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return w.write_all(&[byte]);
+        }
+        w.write_all(&[byte | 0x80])?;
+    }
+}
+
+/// Reads an unsigned LEB128 varint written by `write_varint`
+pub fn read_varint(r: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        r.read_exact(&mut byte)?;
+        // Only the lowest bit of the 10th byte is left for the value
+        if shift == 63 && byte[0] & 0x7f > 1 {
+            return Err(invalid_data("varint is longer than 64 bits"));
+        }
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("varint is longer than 64 bits"))
+}
+
+// Reads exactly `len` bytes
+pub(crate) fn read_bytes(r: &mut impl Read, len: u64) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    r.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+macro_rules! impl_int {
+    ($($int:ty),*) => {
+        $(
+            impl Encode for $int {
+                fn encode(&self, w: &mut impl Write) -> io::Result<()> {
+                    w.write_all(&self.to_le_bytes())
+                }
+            }
+
+            impl Decode for $int {
+                fn decode(r: &mut impl Read) -> io::Result<Self> {
+                    let mut bytes = [0; std::mem::size_of::<$int>()];
+                    r.read_exact(&mut bytes)?;
+                    Ok(<$int>::from_le_bytes(bytes))
+                }
+            }
+        )*
+    };
+}
+
+impl_int!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl Encode for bool {
+    fn encode(&self, w: &mut impl Write) -> io::Result<()> {
+        u8::from(*self).encode(w)
+    }
+}
+
+impl Decode for bool {
+    fn decode(r: &mut impl Read) -> io::Result<Self> {
+        match u8::decode(r)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid_data("bool is neither 0 nor 1")),
+        }
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, w: &mut impl Write) -> io::Result<()> {
+        write_varint(w, self.len() as u64)?;
+        w.write_all(self.as_bytes())
+    }
+}
+
+impl Decode for String {
+    fn decode(r: &mut impl Read) -> io::Result<Self> {
+        let len = read_varint(r)?;
+        String::from_utf8(read_bytes(r, len)?).map_err(|_| invalid_data("string is not UTF-8"))
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, w: &mut impl Write) -> io::Result<()> {
+        write_varint(w, self.len() as u64)?;
+        self.iter().try_for_each(|item| item.encode(w))
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(r: &mut impl Read) -> io::Result<Self> {
+        let len = read_varint(r)?;
+        (0..len).map(|_| T::decode(r)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn varint_round_trip() {
+        let values = [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX];
+
+        let mut bytes = Vec::new();
+        for value in values {
+            assert!(write_varint(&mut bytes, value).is_ok());
+        }
+        // Small values take a single byte
+        assert_eq!(&bytes[..3], &[0, 1, 127]);
+
+        let mut cursor = Cursor::new(bytes);
+        for value in values {
+            assert_eq!(read_varint(&mut cursor).unwrap(), value);
+        }
+        assert!(read_varint(&mut cursor).is_err());
+    }
+
+    #[test]
+    fn varint_rejects_more_than_64_bits() {
+        let max = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert_eq!(read_varint(&mut Cursor::new(max)).unwrap(), u64::MAX);
+
+        // Bits above the 64th in the 10th byte, and an 11th byte
+        let high_bits = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02];
+        let too_long = [
+            0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00,
+        ];
+        for bytes in [&high_bits[..], &too_long[..]] {
+            let error = read_varint(&mut Cursor::new(bytes)).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -1,25 +1,237 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
-    ptr::null_mut,
+    fmt,
+    ops::Deref,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
-use parking_lot::{MappedRwLockReadGuard, RwLock, RwLockReadGuard};
+use parking_lot::{MappedRwLockReadGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-pub type ReadGuard<'a, T> = MappedRwLockReadGuard<'a, T>;
+use crate::{
+    error::TrieError,
+    single_threaded_trie::IntoIter,
+    trie_core::{check_not_empty, ChildMap, DuplicatePolicy, RawTrie, TrieNode},
+};
+
+/// Shared access to a value of a `Trie`, which holds the trie's read lock until dropped.
+///
+/// Writing to the same trie from the thread holding a guard would deadlock; in debug builds it
+/// panics instead.
+pub struct ReadGuard<'a, T> {
+    guard: MappedRwLockReadGuard<'a, T>,
+    #[cfg(debug_assertions)]
+    _held: lock_tracking::HeldLock,
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.guard.fmt(f)
+    }
+}
+
+/// The result of looking up a key that may have no value, holding the trie's read lock like
+/// `ReadGuard`
+pub struct ValueGuard<'a, T>(ReadGuard<'a, Option<T>>);
+
+impl<T> ValueGuard<'_, T> {
+    pub fn as_option(&self) -> Option<&T> {
+        self.0.as_ref()
+    }
+}
+
+impl<T> Deref for ValueGuard<'_, T> {
+    type Target = Option<T>;
+
+    fn deref(&self) -> &Option<T> {
+        &self.0
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ValueGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The trie as seen by the closure of `Trie::transaction`, which holds every shard's write lock.
+///
+/// Its mutations are undone when the closure fails or panics.
+pub struct TxView<'a, T: Send + Sync> {
+    trie: &'a Trie<T>,
+    shards: Vec<RwLockWriteGuard<'a, RawTrie<T>>>,
+    // How to reverse each mutation so far, oldest first
+    undo_log: Vec<Undo<T>>,
+    committed: bool,
+    #[cfg(debug_assertions)]
+    _held: Vec<lock_tracking::HeldLock>,
+}
+
+// The mutation that reverses one made in a transaction
+enum Undo<T> {
+    Remove(String),
+    Insert(String, T),
+}
+
+impl<T: Send + Sync> TxView<'_, T> {
+    pub fn get(&self, key: &str) -> Option<&T> {
+        self.shards[self.trie.shard_of(key)]
+            .get_node(key)?
+            .value
+            .as_ref()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert(&mut self, key: &str, value: T) -> Result<(), TrieError> {
+        let index = self.trie.shard_of(key);
+        self.shards[index].insert(key, value, DuplicatePolicy::Reject)?;
+        self.undo_log.push(Undo::Remove(key.to_owned()));
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Result<(), TrieError> {
+        if key.is_empty() {
+            return Err(TrieError::EmptyKey);
+        }
+        let index = self.trie.shard_of(key);
+        let value = self.shards[index]
+            .remove_value_if(key, |_| true)
+            .ok_or(TrieError::KeyNotFound)?;
+        self.undo_log.push(Undo::Insert(key.to_owned(), value));
+        Ok(())
+    }
+
+    // Keeps the mutations and counts them in `len`
+    fn commit(&mut self) {
+        let inserted = self
+            .undo_log
+            .iter()
+            .filter(|undo| matches!(undo, Undo::Remove(_)))
+            .count();
+        let removed = self.undo_log.len() - inserted;
+        self.trie.len.fetch_add(inserted, Ordering::Release);
+        self.trie.len.fetch_sub(removed, Ordering::Release);
+        self.committed = true;
+    }
+}
+
+impl<T: Send + Sync> Drop for TxView<'_, T> {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        while let Some(undo) = self.undo_log.pop() {
+            match undo {
+                Undo::Remove(key) => {
+                    let index = self.trie.shard_of(&key);
+                    self.shards[index].remove_value_if(&key, |_| true);
+                }
+                Undo::Insert(key, value) => {
+                    let index = self.trie.shard_of(&key);
+                    let _ = self.shards[index].insert(&key, value, DuplicatePolicy::Reject);
+                }
+            }
+        }
+    }
+}
+
+// Records which shards of which tries the current thread holds a lock of, through a
+// `ReadGuard` or a running transaction
+#[cfg(debug_assertions)]
+mod lock_tracking {
+    use std::cell::RefCell;
+
+    #[derive(Clone, Copy, PartialEq)]
+    struct Held {
+        trie: usize,
+        shard: usize,
+        write: bool,
+    }
+
+    thread_local! {
+        static HELD: RefCell<Vec<Held>> = const { RefCell::new(Vec::new()) };
+    }
+
+    pub(super) struct HeldLock(Held);
+
+    impl HeldLock {
+        pub(super) fn read(trie: usize, shard: usize) -> Self {
+            Self::new(Held {
+                trie,
+                shard,
+                write: false,
+            })
+        }
+
+        pub(super) fn write(trie: usize, shard: usize) -> Self {
+            Self::new(Held {
+                trie,
+                shard,
+                write: true,
+            })
+        }
+
+        fn new(held_lock: Held) -> Self {
+            HELD.with(|held| held.borrow_mut().push(held_lock));
+            HeldLock(held_lock)
+        }
+    }
+
+    impl Drop for HeldLock {
+        fn drop(&mut self) {
+            HELD.with(|held| {
+                let mut held = held.borrow_mut();
+                if let Some(index) = held.iter().position(|held_lock| *held_lock == self.0) {
+                    held.swap_remove(index);
+                }
+            });
+        }
+    }
+
+    // Panics if this thread holds a lock of `shard` (of any shard if `None`), or only a write
+    // lock if `write` is false
+    pub(super) fn assert_can_lock(trie: usize, shard: Option<usize>, write: bool) {
+        let conflicts = HELD.with(|held| {
+            held.borrow().iter().any(|held_lock| {
+                held_lock.trie == trie
+                    && shard.is_none_or(|shard| held_lock.shard == shard)
+                    && (write || held_lock.write)
+            })
+        });
+        if conflicts {
+            panic!(
+                "recursive trie lock on same thread: a `ReadGuard` or transaction still holds \
+                 this shard"
+            );
+        }
+    }
+}
 
-#[derive(Debug)]
 pub struct Trie<T: Send + Sync> {
-    top_level_nodes: RwLock<HashMap<char, TrieNode<T>>>,
+    // Each key lives in the shard picked by its first char, so writers to different shards
+    // don't contend
+    shards: Box<[RwLock<RawTrie<T>>]>,
+    // Only modified while a write lock is held
+    len: AtomicUsize,
+    #[cfg(feature = "access-tracking")]
+    track_access: bool,
 }
 
-unsafe impl<T: Send + Sync> Send for Trie<T> {}
-unsafe impl<T: Send + Sync> Sync for Trie<T> {}
+// Shard count used by `Trie::new`
+const DEFAULT_SHARD_COUNT: usize = 16;
 
 impl<T: Send + Sync> Default for Trie<T> {
     fn default() -> Self {
-        Self {
-            top_level_nodes: RwLock::new(HashMap::new()),
-        }
+        Self::with_shards(DEFAULT_SHARD_COUNT)
     }
 }
 
@@ -28,174 +240,589 @@ impl<T: Send + Sync> Trie<T> {
         Self::default()
     }
 
-    pub fn get(&self, key: &str) -> ReadGuard<'_, Option<T>> {
-        let top_level_nodes = self.top_level_nodes.read();
+    /// Creates a trie whose keys are spread over `shard_count` independently locked shards by
+    /// their first char.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shard_count` is 0.
+    pub fn with_shards(shard_count: usize) -> Self {
+        assert!(shard_count > 0, "A trie needs at least one shard");
+        Self {
+            shards: (0..shard_count)
+                .map(|_| RwLock::new(RawTrie::default()))
+                .collect(),
+            len: AtomicUsize::new(0),
+            #[cfg(feature = "access-tracking")]
+            track_access: false,
+        }
+    }
 
-        RwLockReadGuard::map(top_level_nodes, |map: &HashMap<char, TrieNode<T>>| {
-            let key_iter = key.chars();
-            let mut map = map;
-            let mut output = &None;
-            for key in key_iter {
-                if let Some(node) = map.get(&key) {
-                    map = &node.child_nodes;
-                    output = &node.value;
-                } else {
-                    return &None;
-                }
-            }
-            output
+    /// Creates a trie with room for `top_level` distinct first chars without reallocating,
+    /// spread evenly over the shards
+    pub fn with_capacity(top_level: usize) -> Self {
+        let shard_capacity = top_level.div_ceil(DEFAULT_SHARD_COUNT);
+        Self {
+            shards: (0..DEFAULT_SHARD_COUNT)
+                .map(|_| RwLock::new(RawTrie::with_capacity(shard_capacity)))
+                .collect(),
+            len: AtomicUsize::new(0),
+            #[cfg(feature = "access-tracking")]
+            track_access: false,
+        }
+    }
+
+    /// When `true`, every `get`, `get_present` or `get_cloned_if` that finds a value counts
+    /// towards the key's `access_count`. The counters are atomic, so lookups still only take the
+    /// read lock. Off by default.
+    #[cfg(feature = "access-tracking")]
+    pub fn with_access_tracking(mut self, track_access: bool) -> Self {
+        self.track_access = track_access;
+        self
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn get(&self, key: &str) -> ValueGuard<'_, T> {
+        let raw = self.read(key);
+
+        self.value_guard(raw, key)
+    }
+
+    /// Like `get`, but first removes the nodes on the way to `key` that have no values below
+    /// them, e.g. ones left behind when the value for `modify_or_insert` or
+    /// `get_or_insert_with` panicked. Lookups of clean paths only take the read lock.
+    ///
+    /// The read lock is released before taking the write lock, so like any write this must not
+    /// be called while the thread holds a guard of this trie.
+    pub fn get_compacting(&self, key: &str) -> ValueGuard<'_, T> {
+        let raw = self.read(key);
+        if !raw.has_empty_node_along(key) {
+            return self.value_guard(raw, key);
+        }
+        drop(raw);
+
+        // Another writer may have cleaned up or filled the path in between
+        self.write(key).prune_empty_along(key);
+        self.get(key)
+    }
+
+    /// Returns how often `get`, `get_present` or `get_cloned_if` found the value at `key` while
+    /// access tracking was on, or `None` if there is no value.
+    #[cfg(feature = "access-tracking")]
+    pub fn access_count(&self, key: &str) -> Option<u64> {
+        self.read(key).get_node(key)?.access_count()
+    }
+
+    /// Returns a guard over the value at `key`, or `None` if there is no value.
+    ///
+    /// The lock is released right away when the value is absent.
+    pub fn get_present(&self, key: &str) -> Option<ReadGuard<'_, T>> {
+        let raw = self.read(key);
+
+        RwLockReadGuard::try_map(raw, |raw: &RawTrie<T>| {
+            self.lookup(raw, key)?.value.as_ref()
         })
+        .ok()
+        .map(|guard| self.track(key, guard))
+    }
+
+    /// Returns a clone of the value at `key` if `pred` accepts it, releasing the read lock before
+    /// returning, so unlike holding the guard of `get` it is safe to write to the trie afterwards
+    pub fn get_cloned_if(&self, key: &str, pred: impl FnOnce(&T) -> bool) -> Option<T>
+    where
+        T: Clone,
+    {
+        let raw = self.read(key);
+
+        let value = self.lookup(&raw, key)?.value.as_ref()?;
+        pred(value).then(|| value.clone())
+    }
+
+    pub fn insert(&self, key: &str, value: T) -> Result<(), TrieError> {
+        let raw = &mut *(self.write(key));
+
+        raw.insert(key, value, DuplicatePolicy::Reject)?;
+        self.len.fetch_add(1, Ordering::Release);
+        Ok(())
     }
 
-    pub fn insert(&self, key: &str, value: T) -> Result<(), &'static str> {
-        let top_level_nodes = &mut *(self.top_level_nodes.write());
+    /// Inserts `value` only if `key` has no value, and returns whether it did, under one write
+    /// lock. An empty key also returns `false`.
+    pub fn insert_if_absent(&self, key: &str, value: T) -> bool {
+        let raw = &mut *(self.write(key));
+
         if key.is_empty() {
-            return Err("Key can not be empty");
+            return false;
         }
-        let mut key_iter = key.chars();
-        let first_key = key_iter.next().unwrap();
-        //
+        let mut path = raw.path_or_insert(key);
+        if path.node.is_end() {
+            return false;
+        }
+        path.replace_value(value);
+        self.len.fetch_add(1, Ordering::Release);
+        true
+    }
 
-        if let Entry::Vacant(e) = top_level_nodes.entry(first_key) {
-            let new_trie = TrieNode::new_top_level_node();
-            e.insert(new_trie);
+    /// Inserts `value` at `key` and returns a guard over the value now stored there.
+    ///
+    /// The write lock is downgraded to a read lock without being released, so no other writer
+    /// can change the value in between. If `insert` would reject the key, the guard shows what
+    /// is already stored (`None` for an empty key).
+    pub fn insert_then_read(&self, key: &str, value: T) -> ValueGuard<'_, T> {
+        let mut raw = self.write(key);
+
+        if raw.insert(key, value, DuplicatePolicy::Reject).is_ok() {
+            self.len.fetch_add(1, Ordering::Release);
         }
-        let first_trie_node = top_level_nodes.get_mut(&first_key).unwrap();
 
-        first_trie_node.insert(key_iter, value)
+        ValueGuard(self.track(
+            key,
+            RwLockReadGuard::map(RwLockWriteGuard::downgrade(raw), |raw: &RawTrie<T>| {
+                match raw.get_node(key) {
+                    Some(node) => &node.value,
+                    None => &None,
+                }
+            }),
+        ))
     }
 
-    pub fn remove(&self, key: &str) -> Result<(), &'static str> {
-        let top_level_nodes = &mut *(self.top_level_nodes.write());
+    /// Applies `f` to the value at `key`, first inserting the one returned by `default` if there
+    /// is none, all under one write lock.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is empty; `try_modify_or_insert` returns an error instead.
+    pub fn modify_or_insert(&self, key: &str, default: impl FnOnce() -> T, f: impl FnOnce(&mut T)) {
+        let raw = &mut *(self.write(key));
 
-        // ------------------------------------------------
-        // Get node
-        if key.is_empty() {
-            return Err("Key can not be empty");
+        let path = raw.path_or_insert(key);
+        let is_new = !path.node.is_end();
+        // Only count the value once `default` returned it
+        let value = path.get_or_insert_with(default);
+        if is_new {
+            self.len.fetch_add(1, Ordering::Release);
         }
+        f(value);
+    }
 
-        let mut key_iter = key.chars();
-        let first_key = key_iter.next().unwrap();
+    /// Like `modify_or_insert`, but returns `TrieError::EmptyKey` instead of panicking
+    pub fn try_modify_or_insert(
+        &self,
+        key: &str,
+        default: impl FnOnce() -> T,
+        f: impl FnOnce(&mut T),
+    ) -> Result<(), TrieError> {
+        check_not_empty(key)?;
+        self.modify_or_insert(key, default, f);
+        Ok(())
+    }
 
-        let mut current_node = if let Some(trie_node) = top_level_nodes.get_mut(&first_key) {
-            trie_node
-        } else {
-            return Err("No corresponding value");
-        };
+    /// Returns a guard over the value at `key`, first inserting `T::default()` if there is none.
+    ///
+    /// The write lock is downgraded to a read lock without being released, so no other writer
+    /// can remove the value in between.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is empty; `try_get_or_insert_default` returns an error instead.
+    pub fn get_or_insert_default(&self, key: &str) -> ReadGuard<'_, T>
+    where
+        T: Default,
+    {
+        let mut raw = self.write(key);
 
-        // Last parent of the node with only only one child not containing any value (or matching node)
-        let mut parent_node_ptr_and_key = Some((current_node.parent_node_ptr, first_key));
-
-        for key in key_iter {
-            current_node = if let Some(node) = current_node.get_node_mut(&key) {
-                node
-            } else {
-                return Err("No corresponding value");
-            };
-
-            if (current_node.is_end() && !current_node.child_nodes.is_empty())
-                || current_node.child_nodes.len() > 1
-            {
-                parent_node_ptr_and_key = None;
-            } else if parent_node_ptr_and_key.is_none() {
-                parent_node_ptr_and_key = Some((current_node.parent_node_ptr, key));
+        let path = raw.path_or_insert(key);
+        if !path.node.is_end() {
+            path.get_or_insert_with(T::default);
+            self.len.fetch_add(1, Ordering::Release);
+        }
+
+        self.track(
+            key,
+            RwLockReadGuard::map(RwLockWriteGuard::downgrade(raw), |raw: &RawTrie<T>| {
+                raw.get_node(key)
+                    .and_then(|node| node.value.as_ref())
+                    .unwrap()
+            }),
+        )
+    }
+
+    /// Like `get_or_insert_default`, but returns `TrieError::EmptyKey` instead of panicking
+    pub fn try_get_or_insert_default(&self, key: &str) -> Result<ReadGuard<'_, T>, TrieError>
+    where
+        T: Default,
+    {
+        check_not_empty(key)?;
+        Ok(self.get_or_insert_default(key))
+    }
+
+    /// Returns a clone of the value at `key`, first inserting the one returned by `f` if there is
+    /// none, all under one write lock.
+    ///
+    /// `f` is only called when the key is absent. The write lock is downgraded before cloning, so
+    /// readers aren't blocked by the clone and the returned value holds no lock.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is empty; `try_get_or_insert_with` returns an error instead.
+    pub fn get_or_insert_with(&self, key: &str, f: impl FnOnce() -> T) -> T
+    where
+        T: Clone,
+    {
+        let mut raw = self.write(key);
+
+        let path = raw.path_or_insert(key);
+        if !path.node.is_end() {
+            path.get_or_insert_with(f);
+            self.len.fetch_add(1, Ordering::Release);
+        }
+
+        let value = RwLockReadGuard::map(RwLockWriteGuard::downgrade(raw), |raw: &RawTrie<T>| {
+            raw.get_node(key)
+                .and_then(|node| node.value.as_ref())
+                .unwrap()
+        });
+        T::clone(&value)
+    }
+
+    /// Like `get_or_insert_with`, but returns `TrieError::EmptyKey` instead of panicking
+    pub fn try_get_or_insert_with(&self, key: &str, f: impl FnOnce() -> T) -> Result<T, TrieError>
+    where
+        T: Clone,
+    {
+        check_not_empty(key)?;
+        Ok(self.get_or_insert_with(key, f))
+    }
+
+    /// Stores `value` at `key` and returns the value it replaced, if any, under one write lock.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is empty; `try_swap_value` returns an error instead.
+    pub fn swap_value(&self, key: &str, value: T) -> Option<T> {
+        let raw = &mut *(self.write(key));
+
+        let old_value = raw.path_or_insert(key).replace_value(value);
+        if old_value.is_none() {
+            self.len.fetch_add(1, Ordering::Release);
+        }
+        old_value
+    }
+
+    /// Like `swap_value`, but returns `TrieError::EmptyKey` instead of panicking
+    pub fn try_swap_value(&self, key: &str, value: T) -> Result<Option<T>, TrieError> {
+        check_not_empty(key)?;
+        Ok(self.swap_value(key, value))
+    }
+
+    /// Inserts `items`, building the subtrees of distinct first chars in parallel.
+    ///
+    /// Items that `insert` would reject (empty or duplicate keys) are skipped; returns the number
+    /// of items inserted.
+    #[cfg(feature = "rayon")]
+    pub fn par_insert_many(&self, items: Vec<(String, T)>) -> usize {
+        use rayon::prelude::*;
+        use std::collections::HashMap;
+
+        use crate::trie_core::TrieNode;
+
+        let mut shards = self.write_all();
+
+        // Keys with different first chars never share nodes
+        let mut buckets: HashMap<char, Vec<(String, T)>> = HashMap::new();
+        for (key, value) in items {
+            if let Some(first_key) = key.chars().next() {
+                buckets.entry(first_key).or_default().push((key, value));
             }
         }
+        for first_key in buckets.keys() {
+            shards[self.shard_index(*first_key)]
+                .top_level_nodes
+                .entry(*first_key)
+                .or_insert_with(|| TrieNode::with_child_capacity(0));
+        }
+
+        let loads: Vec<_> = shards
+            .iter_mut()
+            .flat_map(|raw| raw.top_level_nodes.iter_mut())
+            .filter_map(|(first_key, node)| buckets.remove(first_key).map(|items| (node, items)))
+            .collect();
+
+        let inserted = loads
+            .into_par_iter()
+            .map(|(node, items)| {
+                items
+                    .into_iter()
+                    .filter_map(|(key, value)| {
+                        node.path_or_insert(key.chars().skip(1), Vec::new(), 0)
+                            .insert(value, DuplicatePolicy::Reject)
+                            .ok()
+                    })
+                    .count()
+            })
+            .sum();
+        self.len.fetch_add(inserted, Ordering::Release);
+        inserted
+    }
 
-        // ------------------------------------------------
-        // Removing
+    pub fn remove(&self, key: &str) -> Result<(), TrieError> {
+        let raw = &mut *(self.write(key));
 
-        if !current_node.is_end() {
-            return Err("No corresponding value");
+        if key.is_empty() {
+            return Err(TrieError::EmptyKey);
         }
 
-        if !current_node.child_nodes.is_empty() {
-            current_node.set_value(None);
-            return Ok(());
+        raw.remove_value_if(key, |_| true)
+            .ok_or(TrieError::KeyNotFound)?;
+        self.len.fetch_sub(1, Ordering::Release);
+        Ok(())
+    }
+
+    /// Removes the value at `key` only if `pred` returns `true` for it; returns whether it removed.
+    ///
+    /// `pred` runs under the write lock, so nothing can change the value in between.
+    pub fn remove_if(&self, key: &str, pred: impl FnOnce(&T) -> bool) -> bool {
+        let raw = &mut *(self.write(key));
+
+        let removed = raw.remove_value_if(key, pred).is_some();
+        if removed {
+            self.len.fetch_sub(1, Ordering::Release);
         }
+        removed
+    }
 
-        if parent_node_ptr_and_key.is_none() {
-            return Ok(());
+    /// Removes the values at `keys` and returns how many were present.
+    ///
+    /// Each shard is write locked once, and its nodes are pruned once after every value in it is
+    /// taken, instead of once per key.
+    pub fn remove_many(&self, keys: &[&str]) -> usize {
+        let mut keys_by_shard = vec![Vec::new(); self.shards.len()];
+        for key in keys {
+            if let Some(first_key) = key.chars().next() {
+                keys_by_shard[self.shard_index(first_key)].push(*key);
+            }
         }
 
-        // SAFETY:
-        unsafe {
-            let parent_node_ptr_and_key = parent_node_ptr_and_key.unwrap();
-            if parent_node_ptr_and_key.0.is_null() {
-                let _ = top_level_nodes.remove(&parent_node_ptr_and_key.1);
-            } else {
-                let parent_node = &mut *parent_node_ptr_and_key.0;
-                let _ = parent_node.child_nodes.remove(&parent_node_ptr_and_key.1);
+        let mut removed = 0;
+        for (index, keys) in keys_by_shard.iter().enumerate() {
+            if !keys.is_empty() {
+                let raw = &mut *(self.write_shard(index));
+                let shard_removed = raw.remove_many(keys, false);
+                self.len.fetch_sub(shard_removed, Ordering::Release);
+                removed += shard_removed;
             }
         }
+        removed
+    }
 
-        Ok(())
+    /// Removes every key.
+    ///
+    /// The nodes are swapped out under the locks and only dropped after releasing them, so
+    /// readers and writers aren't blocked while a large trie is freed.
+    pub fn clear(&self) {
+        let old_shards: Vec<_> = {
+            let mut shards = self.write_all();
+            self.len.store(0, Ordering::Release);
+            shards
+                .iter_mut()
+                .map(|raw| std::mem::take(&mut **raw))
+                .collect()
+        };
+        drop(old_shards);
     }
-}
 
-#[derive(Debug)]
-struct TrieNode<T> {
-    value: Option<T>,
-    child_nodes: HashMap<char, TrieNode<T>>,
-    parent_node_ptr: *mut TrieNode<T>,
-}
+    /// Returns the number of stored values without taking any lock.
+    ///
+    /// It is one atomic counter that every insert and remove updates, so while writers are active
+    /// the count is only as current as the last update this thread sees, and settles on the exact
+    /// count once they stop. The load is `Acquire`, so memory written before the update it reads
+    /// is visible to this thread afterwards.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
 
-impl<T> TrieNode<T> {
-    fn is_end(&self) -> bool {
-        self.value.is_some()
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
-    fn new(parent_node_ptr: *mut TrieNode<T>) -> Self {
-        TrieNode {
-            value: None,
-            child_nodes: HashMap::new(),
-            parent_node_ptr,
+    /// Returns the number of nodes, taking one shard's read lock at a time
+    pub fn node_count(&self) -> usize {
+        let mut count = 0;
+        for index in 0..self.shards.len() {
+            let raw = self.read_shard(index);
+            let mut stack: Vec<_> = raw.top_level_nodes.values().collect();
+            while let Some(node) = stack.pop() {
+                count += 1;
+                stack.extend(node.child_nodes.values());
+            }
         }
+        count
+    }
+
+    /// Reads the same counter as `len`, so it sees the same values, but with a `Relaxed` load that
+    /// orders no other memory with the read. Use it for metrics and monitoring, where nothing else
+    /// is read based on the count.
+    pub fn approximate_len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// Runs `f` with every shard's write lock held, so that its reads and writes of several keys
+    /// happen at once for other threads.
+    ///
+    /// If `f` returns an error or panics, every insert and remove it made is undone before the
+    /// locks are released. Calling other methods of the trie from `f` deadlocks, which debug
+    /// builds turn into a panic.
+    pub fn transaction<E>(
+        &self,
+        f: impl FnOnce(&mut TxView<'_, T>) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let mut view = TxView {
+            trie: self,
+            shards: self.write_all(),
+            undo_log: Vec::new(),
+            committed: false,
+            #[cfg(debug_assertions)]
+            _held: (0..self.shards.len())
+                .map(|shard| lock_tracking::HeldLock::write(self.id(), shard))
+                .collect(),
+        };
+        f(&mut view)?;
+        view.commit();
+        Ok(())
     }
 
-    fn new_top_level_node() -> Self {
-        TrieNode {
-            value: None,
-            child_nodes: HashMap::new(),
-            parent_node_ptr: null_mut(),
+    /// Consumes the trie and returns its keys and values in lexicographic key order.
+    ///
+    /// Owning the trie means no other thread can access it, so no lock is taken.
+    pub fn into_sorted_vec(self) -> Vec<(String, T)> {
+        let mut top_level_nodes = ChildMap::default();
+        for shard in self.shards.into_vec() {
+            // Every first char is in exactly one shard
+            top_level_nodes.extend(shard.into_inner().top_level_nodes);
         }
+        IntoIter::new(top_level_nodes).collect()
     }
 
-    fn as_ptr(&mut self) -> *mut TrieNode<T> {
-        self as *mut _
+    fn shard_index(&self, first_key: char) -> usize {
+        first_key as usize % self.shards.len()
     }
 
-    fn insert<I: Iterator<Item = char>>(
-        &mut self,
-        key_iter: I,
-        value: T,
-    ) -> Result<(), &'static str> {
-        // Base case
-        let mut current_trie = self;
+    // Returns the index of the shard holding `key`; empty keys go to the first one
+    fn shard_of(&self, key: &str) -> usize {
+        key.chars()
+            .next()
+            .map_or(0, |first_key| self.shard_index(first_key))
+    }
 
-        for key in key_iter {
-            let node_pointer = current_trie.as_ptr();
-            if let Entry::Vacant(e) = current_trie.child_nodes.entry(key) {
-                let new_trie = TrieNode::new(node_pointer);
-                e.insert(new_trie);
-            }
-            let trie = current_trie.child_nodes.get_mut(&key).unwrap();
-            current_trie = trie;
+    // Maps the read lock of the shard holding `key` to its value
+    fn value_guard<'a>(
+        &'a self,
+        raw: RwLockReadGuard<'a, RawTrie<T>>,
+        key: &str,
+    ) -> ValueGuard<'a, T> {
+        ValueGuard(self.track(
+            key,
+            RwLockReadGuard::map(raw, |raw: &RawTrie<T>| match self.lookup(raw, key) {
+                Some(node) => &node.value,
+                None => &None,
+            }),
+        ))
+    }
+
+    // Finds the node at `key` for a caller's lookup, counting the access if tracking is on
+    fn lookup<'a>(&self, raw: &'a RawTrie<T>, key: &str) -> Option<&'a TrieNode<T>> {
+        let node = raw.get_node(key)?;
+        #[cfg(feature = "access-tracking")]
+        if self.track_access {
+            node.record_access();
         }
-        if current_trie.is_end() {
-            Err("Duplicate keys are not allowed")
-        } else {
-            current_trie.value = Some(value);
-            Ok(())
+        Some(node)
+    }
+
+    // Takes the read lock of the shard holding `key`
+    fn read(&self, key: &str) -> RwLockReadGuard<'_, RawTrie<T>> {
+        self.read_shard(self.shard_of(key))
+    }
+
+    fn read_shard(&self, index: usize) -> RwLockReadGuard<'_, RawTrie<T>> {
+        self.assert_can_lock(Some(index), false);
+        self.shards[index].read()
+    }
+
+    // Takes the write lock of the shard holding `key`
+    fn write(&self, key: &str) -> RwLockWriteGuard<'_, RawTrie<T>> {
+        self.write_shard(self.shard_of(key))
+    }
+
+    fn write_shard(&self, index: usize) -> RwLockWriteGuard<'_, RawTrie<T>> {
+        self.assert_can_lock(Some(index), true);
+        self.shards[index].write()
+    }
+
+    // Takes every shard's write lock, always in the same order so that two callers can't
+    // deadlock
+    fn write_all(&self) -> Vec<RwLockWriteGuard<'_, RawTrie<T>>> {
+        self.assert_can_lock(None, true);
+        self.shards.iter().map(RwLock::write).collect()
+    }
+
+    // Panics in debug builds if taking the lock of shard `index` (of every shard if `None`)
+    // would deadlock on a lock this thread holds
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    fn assert_can_lock(&self, index: Option<usize>, write: bool) {
+        #[cfg(debug_assertions)]
+        lock_tracking::assert_can_lock(self.id(), index, write);
+    }
+
+    // Identifies the trie in the lock tracking of debug builds
+    #[cfg(debug_assertions)]
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    // Wraps a guard derived from the read lock of the shard holding `key`
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    fn track<'a, U>(&'a self, key: &str, guard: MappedRwLockReadGuard<'a, U>) -> ReadGuard<'a, U> {
+        ReadGuard {
+            guard,
+            #[cfg(debug_assertions)]
+            _held: lock_tracking::HeldLock::read(self.id(), self.shard_of(key)),
         }
     }
+}
 
-    fn set_value(&mut self, value: Option<T>) {
-        self.value = value;
+impl<T: Send + Sync + fmt::Debug> fmt::Debug for Trie<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Trie");
+        // Don't block (or deadlock) when a writer holds a lock
+        match self
+            .shards
+            .iter()
+            .map(RwLock::try_read)
+            .collect::<Option<Vec<_>>>()
+        {
+            Some(shards) => d.field("top_level_nodes", &ShardedNodes(&shards)),
+            None => d.field("top_level_nodes", &format_args!("<locked>")),
+        };
+        d.finish()
     }
+}
+
+// Formats the top level nodes of every shard as one map ordered by `char`
+struct ShardedNodes<'a, 'b, T>(&'a [RwLockReadGuard<'b, RawTrie<T>>]);
 
-    fn get_node_mut(&mut self, key: &char) -> Option<&mut TrieNode<T>> {
-        self.child_nodes.get_mut(key)
+impl<T: fmt::Debug> fmt::Debug for ShardedNodes<'_, '_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut nodes: Vec<_> = self
+            .0
+            .iter()
+            .flat_map(|raw| raw.top_level_nodes.iter())
+            .collect();
+        nodes.sort_unstable_by_key(|(key, _)| **key);
+        f.debug_map().entries(nodes).finish()
     }
 }
 
@@ -207,16 +834,17 @@ mod tests {
 
     #[test]
     fn read_works() {
-        let mut trie = Trie::new();
-        trie.insert("hello", 99);
-        trie.insert("hello", 23);
-        trie.insert("bag", 11);
-        trie.insert("bucket", 9);
+        let trie = Trie::new();
+        assert!(trie.insert("hello", 99).is_ok());
+        assert!(trie.insert("hello", 23).is_err());
+        assert!(trie.insert("bag", 11).is_ok());
+        assert!(trie.insert("bucket", 9).is_ok());
         let trie = Arc::new(trie);
 
+        let mut handles = Vec::new();
         for n in 0..20 {
             let trie = trie.clone();
-            std::thread::spawn(move || {
+            handles.push(std::thread::spawn(move || {
                 assert_eq!(trie.get("hello").deref(), &Some(99));
                 assert_eq!(trie.get("bag").deref(), &Some(11));
                 println!(
@@ -224,14 +852,17 @@ mod tests {
                     trie.get("hello").deref().unwrap(),
                     n
                 );
-            });
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
         }
     }
 
     #[test]
     fn insert_multiple_values() {
         // Create new `Trie`
-        let mut trie = Trie::new();
+        let trie = Trie::new();
 
         // Insert values
         assert!(trie.insert("hello", 1).is_ok());
@@ -261,15 +892,777 @@ mod tests {
     fn write_works() {
         let trie = Arc::new(Trie::new());
 
-        trie.insert("hello", 99);
+        assert!(trie.insert("hello", 99).is_ok());
 
         let trie_thread = trie.clone();
         std::thread::spawn(move || {
             println!("Value of key `hello` is {:?}", trie_thread.get("hello"));
-            trie_thread.remove("hello");
+            assert!(trie_thread.remove("hello").is_ok());
         })
-        .join();
+        .join()
+        .unwrap();
 
         assert_eq!(trie.get("hello").deref(), &None);
     }
+
+    #[test]
+    fn debug_output_is_deterministic() {
+        // Create two `Trie`s with the same keys inserted in different orders
+        let trie = Trie::new();
+        let other_trie = Trie::new();
+        let keys = ["hello", "hell", "hey", "back", "bag", "zebra", "apple"];
+        for (value, key) in keys.iter().enumerate() {
+            assert!(trie.insert(key, value).is_ok());
+        }
+        for (value, key) in keys.iter().enumerate().rev() {
+            assert!(other_trie.insert(key, value).is_ok());
+        }
+
+        // Format the same trie twice
+        assert_eq!(format!("{:?}", &trie), format!("{:?}", &trie));
+
+        // Insertion order doesn't matter
+        assert_eq!(format!("{:?}", &trie), format!("{:?}", &other_trie));
+    }
+
+    #[test]
+    fn debug_output_has_no_addresses() {
+        // Create new `Trie`
+        let trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("hel", 2).is_ok());
+        assert!(trie.insert("back", 3).is_ok());
+
+        // Parent pointers are not printed
+        let output = format!("{:?}", &trie);
+        assert!(!output.contains("0x"));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_insert_many_matches_sequential_insert() {
+        let items: Vec<(String, usize)> = (0..50_000)
+            .map(|n| {
+                let first_key = char::from(b'a' + (n % 26) as u8);
+                // Every 1000th key repeats an earlier one
+                (format!("{}{}", first_key, n % 49_000), n)
+            })
+            .collect();
+
+        let sequential_trie = Trie::new();
+        let parallel_trie = Trie::new();
+        assert!(sequential_trie.insert("a0", 0).is_ok());
+        assert!(parallel_trie.insert("a0", 0).is_ok());
+
+        let mut inserted = 0;
+        for (key, value) in items.clone() {
+            if sequential_trie.insert(&key, value).is_ok() {
+                inserted += 1;
+            }
+        }
+        assert_eq!(parallel_trie.par_insert_many(items.clone()), inserted);
+
+        for (key, _) in &items {
+            assert_eq!(*parallel_trie.get(key), *sequential_trie.get(key));
+        }
+    }
+
+    #[test]
+    fn remove_if_checks_value() {
+        // Create new `Trie`
+        let trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("positive", 5).is_ok());
+        assert!(trie.insert("negative", -5).is_ok());
+
+        // Only negative values are removed
+        assert!(!trie.remove_if("positive", |value| *value < 0));
+        assert!(trie.remove_if("negative", |value| *value < 0));
+        assert!(!trie.remove_if("negative", |value| *value < 0));
+        assert!(!trie.remove_if("missing", |value| *value < 0));
+
+        // Get values
+        assert_eq!(*trie.get("positive"), Some(5));
+        assert_eq!(*trie.get("negative"), None);
+    }
+
+    #[test]
+    fn remove_keeps_sibling_keys() {
+        // Create new `Trie`
+        let trie = Trie::new();
+
+        // Insert values sharing a top level node, then enough others to make maps grow
+        assert!(trie.insert("hx", 1).is_ok());
+        assert!(trie.insert("hy", 2).is_ok());
+        for key in ["ha", "hb", "hc", "hd", "a", "b", "c", "d", "e", "f", "g"] {
+            assert!(trie.insert(key, 0).is_ok());
+        }
+
+        // Delete value
+        assert!(trie.remove("hy").is_ok());
+
+        // Get values
+        assert_eq!(*trie.get("hx"), Some(1));
+        assert_eq!(*trie.get("hy"), None);
+        assert_eq!(*trie.get("ha"), Some(0));
+    }
+
+    #[test]
+    fn approximate_len_converges() {
+        let trie = Arc::new(Trie::new());
+
+        let mut handles = Vec::new();
+        for n in 0..8 {
+            let trie = trie.clone();
+            handles.push(std::thread::spawn(move || {
+                for i in 0..500 {
+                    let key = format!("{}-{}", n, i);
+                    assert!(trie.insert(&key, i).is_ok());
+                    if i % 5 == 0 {
+                        assert!(trie.remove(&key).is_ok());
+                    }
+                }
+            }));
+        }
+
+        // Never more than what was inserted, even while writers are active
+        for _ in 0..100 {
+            assert!(trie.approximate_len() <= 8 * 500);
+        }
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(trie.len(), 8 * 400);
+        assert_eq!(trie.approximate_len(), trie.len());
+    }
+
+    #[test]
+    fn get_present() {
+        // Create new `Trie`
+        let trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("hel", 2).is_ok());
+        assert!(trie.insert("help", 3).is_ok());
+
+        // Present keys give a guard over the value itself
+        assert_eq!(trie.get_present("hello").as_deref(), Some(&1));
+        assert_eq!(*trie.get_present("hel").unwrap(), 2);
+
+        // Absent keys don't hold on to the lock
+        assert!(trie.get_present("he").is_none());
+        assert!(trie.get_present("help!").is_none());
+        assert!(trie.get_present("").is_none());
+        assert!(trie.remove("help").is_ok());
+        assert!(trie.get_present("help").is_none());
+    }
+
+    #[test]
+    fn remove_many_counts_removed_values() {
+        // Create new `Trie`
+        let trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("hell", 2).is_ok());
+        assert!(trie.insert("back", 3).is_ok());
+
+        // Only present keys count
+        assert_eq!(trie.remove_many(&["hello", "hel", "back", "missing"]), 2);
+        assert_eq!(trie.len(), 1);
+
+        // Get values
+        assert_eq!(*trie.get("hello"), None);
+        assert_eq!(*trie.get("back"), None);
+        assert_eq!(*trie.get("hell"), Some(2));
+    }
+
+    #[test]
+    fn swap_value_returns_old_value() {
+        // Create new `Trie`
+        let trie = Trie::new();
+
+        // Absent keys are inserted
+        assert_eq!(trie.swap_value("hello", 1), None);
+        assert_eq!(trie.swap_value("hello", 2), Some(1));
+        assert_eq!(trie.swap_value("hell", 3), None);
+        assert_eq!(trie.len(), 2);
+
+        // Get values
+        assert_eq!(*trie.get("hello"), Some(2));
+        assert_eq!(*trie.get("hell"), Some(3));
+    }
+
+    #[test]
+    fn insert_then_read_observes_inserted_value() {
+        let trie = Arc::new(Trie::new());
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+        // Writers keep removing the keys as soon as they appear
+        let mut handles = Vec::new();
+        for _ in 0..4 {
+            let trie = trie.clone();
+            let done = done.clone();
+            handles.push(std::thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    for i in 0..100 {
+                        trie.remove_if(&format!("key-{}", i), |_| true);
+                    }
+                }
+            }));
+        }
+
+        for i in 0..100 {
+            let guard = trie.insert_then_read(&format!("key-{}", i), i);
+            assert_eq!(*guard, Some(i));
+        }
+        done.store(true, Ordering::Relaxed);
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // Rejected inserts show the stored value
+        assert!(trie.insert("hello", 1).is_ok());
+        assert_eq!(*trie.insert_then_read("hello", 2), Some(1));
+        assert_eq!(*trie.insert_then_read("", 3), None);
+    }
+
+    #[test]
+    fn modify_or_insert_counts_every_call() {
+        let trie = Arc::new(Trie::new());
+
+        let mut handles = Vec::new();
+        for _ in 0..8 {
+            let trie = trie.clone();
+            handles.push(std::thread::spawn(move || {
+                for _ in 0..500 {
+                    trie.modify_or_insert("counter", || 0, |count| *count += 1);
+                }
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(*trie.get("counter"), Some(8 * 500));
+        assert_eq!(trie.len(), 1);
+    }
+
+    #[test]
+    fn get_or_insert_default_observes_a_value() {
+        let trie = Arc::new(Trie::<i32>::new());
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+        // A writer keeps removing the key as soon as it appears
+        let remover = {
+            let trie = trie.clone();
+            let done = done.clone();
+            std::thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    trie.remove_if("counter", |_| true);
+                }
+            })
+        };
+
+        let mut handles = Vec::new();
+        for _ in 0..4 {
+            let trie = trie.clone();
+            handles.push(std::thread::spawn(move || {
+                for _ in 0..500 {
+                    assert_eq!(*trie.get_or_insert_default("counter"), 0);
+                }
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+        remover.join().unwrap();
+
+        // An existing value is kept
+        assert!(trie.insert("hello", 1).is_ok());
+        assert_eq!(*trie.get_or_insert_default("hello"), 1);
+        assert!(trie.len() <= 2);
+    }
+
+    #[test]
+    fn get_or_insert_with_calls_f_once() {
+        let trie = Arc::new(Trie::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let mut handles = Vec::new();
+        for i in 0..8 {
+            let trie = trie.clone();
+            let calls = calls.clone();
+            handles.push(std::thread::spawn(move || {
+                let mut seen = Vec::new();
+                for _ in 0..100 {
+                    seen.push(trie.get_or_insert_with("hello", || {
+                        calls.fetch_add(1, Ordering::Relaxed);
+                        i
+                    }));
+                }
+                seen
+            }));
+        }
+
+        let mut values = Vec::new();
+        for handle in handles {
+            values.extend(handle.join().unwrap());
+        }
+
+        // Every thread got the value of the one call to `f`
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert!(values.iter().all(|value| *value == values[0]));
+        assert_eq!(*trie.get("hello"), Some(values[0]));
+        assert_eq!(trie.len(), 1);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "recursive trie lock on same thread")]
+    fn writing_while_holding_a_guard_panics() {
+        // Create new `Trie`
+        let trie = Trie::new();
+        assert!(trie.insert("hello", 1).is_ok());
+
+        let guard = trie.get("hello");
+        assert!(trie.insert("help", 2).is_ok());
+        drop(guard);
+    }
+
+    #[test]
+    fn writing_to_another_shard_while_holding_a_guard() {
+        // Create new `Trie`
+        let trie = Trie::with_shards(2);
+        assert!(trie.insert("a", 1).is_ok());
+
+        // 'a' and 'b' are in different shards, so writing 'b' doesn't wait for the guard
+        let guard = trie.get("a");
+        assert!(trie.insert("b", 2).is_ok());
+        assert!(trie.remove("b").is_ok());
+        assert_eq!(*guard, Some(1));
+        drop(guard);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "recursive trie lock on same thread")]
+    fn write_all_while_holding_a_guard_panics() {
+        // Create new `Trie`
+        let trie: Trie<i32> = Trie::with_shards(2);
+        assert!(trie.insert("a", 1).is_ok());
+
+        let guard = trie.get("a");
+        let _ = trie.transaction(|_| Ok::<_, ()>(()));
+        drop(guard);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "recursive trie lock on same thread")]
+    fn using_the_trie_inside_a_transaction_panics() {
+        // Create new `Trie`
+        let trie: Trie<i32> = Trie::new();
+
+        let _ = trie.transaction(|_| {
+            let _ = trie.get("hello");
+            Ok::<_, ()>(())
+        });
+    }
+
+    #[test]
+    fn writing_after_dropping_guards() {
+        // Create new `Trie`s
+        let trie = Trie::new();
+        let other = Trie::new();
+        assert!(trie.insert("hello", 1).is_ok());
+
+        {
+            let _guard = trie.get("hello");
+            let _present = trie.get_present("hello");
+            // Guards only block writes to their own trie
+            assert!(other.insert("hello", 2).is_ok());
+        }
+        assert!(trie.remove("hello").is_ok());
+    }
+
+    #[test]
+    fn writers_to_different_shards_do_not_block() {
+        use std::{sync::mpsc, time::Duration};
+
+        let trie = Arc::new(Trie::with_shards(4));
+        assert!(trie.insert("apple", 1).is_ok());
+
+        // Hold the read lock of the shard with the keys starting with 'a'
+        let guard = trie.get("apple");
+
+        let (sender, receiver) = mpsc::channel();
+        for key in ["banana", "avocado"] {
+            let trie = trie.clone();
+            let sender = sender.clone();
+            std::thread::spawn(move || {
+                assert!(trie.insert(key, 2).is_ok());
+                sender.send(key).unwrap();
+            });
+        }
+
+        // 'b' is in another shard, 'a' has to wait for the guard
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(10)), Ok("banana"));
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(guard);
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(10)),
+            Ok("avocado")
+        );
+
+        assert_eq!(trie.len(), 3);
+        assert_eq!(trie.shard_count(), 4);
+    }
+
+    #[test]
+    fn into_sorted_vec_orders_keys() {
+        let trie = Arc::new(Trie::with_shards(3));
+
+        let mut handles = Vec::new();
+        for key in ["world", "hello", "help", "a", "hat", "word"] {
+            let trie = trie.clone();
+            handles.push(std::thread::spawn(move || {
+                assert!(trie.insert(key, key.len()).is_ok());
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let trie = Arc::into_inner(trie).unwrap();
+        assert_eq!(
+            trie.into_sorted_vec(),
+            vec![
+                (String::from("a"), 1),
+                (String::from("hat"), 3),
+                (String::from("hello"), 5),
+                (String::from("help"), 4),
+                (String::from("word"), 4),
+                (String::from("world"), 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn value_guard() {
+        // Create new `Trie`
+        let trie = Trie::new();
+        assert!(trie.insert("hello", 1).is_ok());
+
+        let guard: ValueGuard<'_, i32> = trie.get("hello");
+        assert_eq!(*guard, Some(1));
+        assert_eq!(guard.as_option(), Some(&1));
+        assert_eq!(format!("{:?}", guard), "Some(1)");
+        drop(guard);
+
+        assert_eq!(trie.get("world").as_option(), None);
+        assert!(trie.get("world").is_none());
+    }
+
+    #[test]
+    fn with_capacity_preallocates_top_level() {
+        // Create new `Trie`
+        let trie = Trie::with_capacity(26);
+        let capacities = |trie: &Trie<i32>| {
+            trie.shards
+                .iter()
+                .map(|shard| shard.read().top_level_nodes.capacity())
+                .collect::<Vec<_>>()
+        };
+        let before = capacities(&trie);
+
+        for key in 'a'..='z' {
+            assert!(trie.insert(&format!("{}bc", key), 1).is_ok());
+        }
+        assert_eq!(capacities(&trie), before);
+        assert_eq!(*trie.get("zbc"), Some(1));
+        assert_eq!(trie.len(), 26);
+    }
+
+    #[test]
+    fn clear_drops_nodes_after_unlocking() {
+        use std::{
+            sync::{mpsc, Mutex},
+            time::{Duration, Instant},
+        };
+
+        // Pauses while being dropped until the test resumes it
+        struct PausingDrop {
+            dropping: mpsc::Sender<()>,
+            resume: Mutex<mpsc::Receiver<()>>,
+        }
+
+        impl Drop for PausingDrop {
+            fn drop(&mut self) {
+                self.dropping.send(()).unwrap();
+                let _ = self
+                    .resume
+                    .lock()
+                    .unwrap()
+                    .recv_timeout(Duration::from_secs(10));
+            }
+        }
+
+        let (dropping_sender, dropping) = mpsc::channel();
+        let (resume, resume_receiver) = mpsc::channel();
+        let trie = Arc::new(Trie::new());
+        let value = PausingDrop {
+            dropping: dropping_sender,
+            resume: Mutex::new(resume_receiver),
+        };
+        assert!(trie.insert("hello", value).is_ok());
+
+        let handle = {
+            let trie = trie.clone();
+            std::thread::spawn(move || trie.clear())
+        };
+
+        // The value is being dropped, and the trie can already be used
+        dropping.recv_timeout(Duration::from_secs(10)).unwrap();
+        let start = Instant::now();
+        assert!(trie.is_empty());
+        assert!(trie.get("hello").is_none());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        resume.send(()).unwrap();
+        handle.join().unwrap();
+        assert!(trie.is_empty());
+    }
+
+    #[cfg(feature = "access-tracking")]
+    #[test]
+    fn access_count_counts_lookups() {
+        let trie = Arc::new(Trie::new().with_access_tracking(true));
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("help", 2).is_ok());
+
+        let mut handles = Vec::new();
+        for _ in 0..8 {
+            let trie = trie.clone();
+            handles.push(std::thread::spawn(move || {
+                for _ in 0..100 {
+                    assert_eq!(*trie.get("hello"), Some(1));
+                    assert!(trie.get_present("hel").is_none());
+                }
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(*trie.get_present("help").unwrap(), 2);
+
+        assert_eq!(trie.access_count("hello"), Some(8 * 100));
+        assert_eq!(trie.access_count("help"), Some(1));
+        assert_eq!(trie.access_count("hel"), None);
+    }
+
+    #[test]
+    fn len_counts_net_insertions() {
+        let trie = Arc::new(Trie::new());
+        let net = Arc::new(AtomicUsize::new(0));
+
+        // Threads race on the same keys, so many inserts and removes fail
+        let mut handles = Vec::new();
+        for n in 0..8 {
+            let trie = trie.clone();
+            let net = net.clone();
+            handles.push(std::thread::spawn(move || {
+                for i in 0..500 {
+                    let key = format!("key-{}", (i * 7 + n) % 100);
+                    if (i + n) % 3 == 0 {
+                        if trie.remove(&key).is_ok() {
+                            net.fetch_sub(1, Ordering::Relaxed);
+                        }
+                    } else if trie.insert(&key, i).is_ok() {
+                        net.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(trie.len(), net.load(Ordering::Relaxed));
+        assert_eq!(trie.approximate_len(), trie.len());
+        // The count matches the values behind the locks
+        let stored: usize = (0..trie.shard_count())
+            .map(|index| {
+                let raw = trie.read_shard(index);
+                let counts = raw.top_level_nodes.values();
+                counts.map(|node| node.subtree_value_count).sum::<usize>()
+            })
+            .sum();
+        assert_eq!(trie.len(), stored);
+        let trie = Arc::into_inner(trie).unwrap();
+        assert_eq!(trie.into_sorted_vec().len(), net.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn failed_transaction_rolls_back() {
+        let trie = Trie::new();
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("help", 2).is_ok());
+        let before = format!("{:?}", trie);
+
+        // Fails after changing several keys in different shards
+        let result = trie.transaction(|tx| {
+            tx.remove("hello")?;
+            tx.insert("hel", 3)?;
+            tx.insert("world", 4)?;
+            tx.remove("help")?;
+            tx.insert("hello", 5)?;
+            assert_eq!(tx.get("hello"), Some(&5));
+            tx.insert("world", 6)
+        });
+        assert_eq!(result, Err(TrieError::DuplicateKey));
+        assert_eq!(format!("{:?}", trie), before);
+        assert_eq!(trie.len(), 2);
+
+        // A transaction that succeeds keeps its changes
+        let result = trie.transaction(|tx| {
+            tx.remove("hello")?;
+            tx.insert("world", 4)?;
+            tx.insert("wor", 5)
+        });
+        assert_eq!(result, Ok(()));
+        assert_eq!(*trie.get("hello"), None);
+        assert_eq!(*trie.get("wor"), Some(5));
+        assert_eq!(trie.len(), 3);
+        assert_eq!(trie.approximate_len(), 3);
+    }
+
+    #[test]
+    fn panicking_transaction_rolls_back() {
+        let trie = Arc::new(Trie::new());
+        assert!(trie.insert("hello", 1).is_ok());
+
+        let result = {
+            let trie = trie.clone();
+            std::thread::spawn(move || {
+                trie.transaction(|tx| -> Result<(), TrieError> {
+                    tx.remove("hello")?;
+                    tx.insert("world", 2)?;
+                    panic!("transaction failed");
+                })
+            })
+            .join()
+        };
+        assert!(result.is_err());
+
+        assert_eq!(*trie.get("hello"), Some(1));
+        assert_eq!(*trie.get("world"), None);
+        assert_eq!(trie.len(), 1);
+    }
+
+    #[test]
+    fn insert_if_absent_inserts_once() {
+        let trie = Arc::new(Trie::new());
+
+        let mut handles = Vec::new();
+        for n in 0..8 {
+            let trie = trie.clone();
+            handles.push(std::thread::spawn(move || {
+                trie.insert_if_absent("hello", n)
+            }));
+        }
+        let inserted = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .filter(|inserted| *inserted)
+            .count();
+
+        assert_eq!(inserted, 1);
+        assert!(!trie.insert_if_absent("hello", 9));
+        assert!(!trie.insert_if_absent("", 9));
+        assert!(trie.get("hello").is_some_and(|value| value != 9));
+        assert_eq!(trie.len(), 1);
+    }
+
+    #[test]
+    fn try_variants_reject_empty_keys_without_panicking() {
+        let trie = Trie::new();
+
+        assert_eq!(
+            trie.try_modify_or_insert("", || 1, |value| *value += 1),
+            Err(TrieError::EmptyKey)
+        );
+        assert!(matches!(
+            trie.try_get_or_insert_default(""),
+            Err(TrieError::EmptyKey)
+        ));
+        assert_eq!(
+            trie.try_get_or_insert_with("", || 1),
+            Err(TrieError::EmptyKey)
+        );
+        assert_eq!(trie.try_swap_value("", 1), Err(TrieError::EmptyKey));
+        assert!(trie.is_empty());
+
+        // Unusual but non-empty keys work like any other key
+        let long_key = "ü".repeat(10_000);
+        for key in ["\0", "e\u{301}", "\u{10FFFF}", &long_key] {
+            assert_eq!(trie.try_get_or_insert_with(key, || 1), Ok(1));
+            assert_eq!(trie.try_swap_value(key, 2), Ok(Some(1)));
+            assert!(trie
+                .try_modify_or_insert(key, || 0, |value| *value += 1)
+                .is_ok());
+            assert_eq!(*trie.try_get_or_insert_default(key).unwrap(), 3);
+        }
+        assert_eq!(trie.len(), 4);
+    }
+
+    #[test]
+    fn get_cloned_if_then_insert_does_not_deadlock() {
+        let trie = Trie::new();
+        assert!(trie.insert("hello", 1).is_ok());
+
+        // Check a value, then write to the same shard on the same thread
+        if let Some(value) = trie.get_cloned_if("hello", |value| *value > 0) {
+            assert!(trie.insert("hello!", value + 1).is_ok());
+            assert_eq!(trie.swap_value("hello", value + 10), Some(1));
+        }
+        assert_eq!(trie.get_cloned_if("hello!", |_| true), Some(2));
+        assert_eq!(trie.get_cloned_if("hello", |value| *value == 1), None);
+        assert_eq!(trie.get_cloned_if("hello", |value| *value == 11), Some(11));
+        assert_eq!(trie.get_cloned_if("hel", |_| true), None);
+    }
+
+    #[test]
+    fn get_compacting_prunes_nodes_left_by_a_panic() {
+        let trie = Trie::new();
+        assert!(trie.insert("hello", 1).is_ok());
+        assert_eq!(trie.node_count(), 5);
+
+        // Making the value panics after the nodes for the key were created
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            trie.modify_or_insert("help/me", || panic!("no value"), |_| {});
+        }));
+        assert!(result.is_err());
+        assert_eq!(trie.node_count(), 9);
+        assert_eq!(*trie.get("help/me"), None);
+        assert_eq!(trie.node_count(), 9);
+
+        // Clean paths are left alone
+        assert_eq!(*trie.get_compacting("hello"), Some(1));
+        assert_eq!(*trie.get_compacting("hel"), None);
+        assert_eq!(trie.node_count(), 9);
+
+        // Looking up any key through the cruft removes all of it
+        assert_eq!(*trie.get_compacting("help"), None);
+        assert_eq!(trie.node_count(), 5);
+        assert_eq!(*trie.get_compacting("hello"), Some(1));
+        assert_eq!(trie.len(), 1);
+    }
 }
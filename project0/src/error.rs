@@ -0,0 +1,40 @@
+use std::{error::Error, fmt};
+
+/// Errors returned by the fallible trie operations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrieError {
+    /// The key is the empty string
+    EmptyKey,
+    /// The key already has a value and the trie rejects duplicates
+    DuplicateKey,
+    /// The key has no value
+    KeyNotFound,
+    /// The key has `len` chars, more than the configured `max`
+    KeyTooLong { len: usize, max: usize },
+    /// The key is a prefix of a stored key, or has one as a prefix, and the trie is terminal only
+    PrefixConflict,
+    /// The key needs a node to have more children than the configured maximum fanout
+    FanoutExceeded,
+    /// The key needs more nodes below its first char than a node capped trie holds in total
+    NodeCapExceeded,
+}
+
+impl fmt::Display for TrieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrieError::EmptyKey => f.write_str("Key can not be empty"),
+            TrieError::DuplicateKey => f.write_str("Duplicate keys are not allowed"),
+            TrieError::KeyNotFound => f.write_str("No corresponding value"),
+            TrieError::KeyTooLong { len, max } => {
+                write!(f, "Key has {} chars, more than the maximum of {}", len, max)
+            }
+            TrieError::PrefixConflict => f.write_str("Keys can not be prefixes of each other"),
+            TrieError::FanoutExceeded => f.write_str("A node can not have any more children"),
+            TrieError::NodeCapExceeded => {
+                f.write_str("The keys sharing this first char can not fit in the node cap")
+            }
+        }
+    }
+}
+
+impl Error for TrieError {}
@@ -0,0 +1,117 @@
+use std::time::{Duration, Instant};
+
+use crate::{error::TrieError, single_threaded_trie::Trie};
+
+/// A `Trie` whose values can expire.
+///
+/// Expired values are treated as absent and are removed lazily when their key is accessed, or
+/// eagerly by `purge_expired`.
+pub struct ExpiringTrie<T> {
+    trie: Trie<(T, Option<Instant>)>,
+}
+
+impl<T> Default for ExpiringTrie<T> {
+    fn default() -> Self {
+        Self { trie: Trie::new() }
+    }
+}
+
+impl<T> ExpiringTrie<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<&T> {
+        self.remove_if_expired(key);
+        self.trie.get(key).map(|(value, _)| value)
+    }
+
+    pub fn contains_key(&mut self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Inserts a value that never expires
+    pub fn insert(&mut self, key: &str, value: T) -> Result<(), TrieError> {
+        self.remove_if_expired(key);
+        self.trie.insert(key, (value, None))
+    }
+
+    /// Inserts a value that expires once `ttl` has elapsed
+    pub fn insert_with_ttl(&mut self, key: &str, value: T, ttl: Duration) -> Result<(), TrieError> {
+        self.remove_if_expired(key);
+        self.trie.insert(key, (value, Some(Instant::now() + ttl)))
+    }
+
+    pub fn remove(&mut self, key: &str) -> Result<(), TrieError> {
+        self.remove_if_expired(key);
+        self.trie.remove(key)
+    }
+
+    /// Removes every expired value
+    pub fn purge_expired(&mut self) {
+        let now = Instant::now();
+        self.trie.retain(|_, (_, expiry)| !is_expired(*expiry, now));
+    }
+
+    fn remove_if_expired(&mut self, key: &str) {
+        let now = Instant::now();
+        self.trie
+            .remove_if(key, |(_, expiry)| is_expired(*expiry, now));
+    }
+}
+
+fn is_expired(expiry: Option<Instant>, now: Instant) -> bool {
+    expiry.is_some_and(|expiry| expiry <= now)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use super::*;
+
+    #[test]
+    fn values_expire_after_ttl() {
+        // Create new `ExpiringTrie`
+        let mut trie = ExpiringTrie::new();
+
+        // Insert values
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie
+            .insert_with_ttl("hey", 2, Duration::from_millis(20))
+            .is_ok());
+        assert_eq!(trie.get("hey"), Some(&2));
+
+        sleep(Duration::from_millis(40));
+
+        // Get values
+        assert_eq!(trie.get("hello"), Some(&1));
+        assert_eq!(trie.get("hey"), None);
+        assert!(!trie.contains_key("hey"));
+
+        // An expired key can be inserted again
+        assert!(trie.insert("hey", 3).is_ok());
+        assert_eq!(trie.get("hey"), Some(&3));
+    }
+
+    #[test]
+    fn purge_expired_removes_values() {
+        // Create new `ExpiringTrie`
+        let mut trie = ExpiringTrie::new();
+
+        // Insert values
+        assert!(trie
+            .insert_with_ttl("hello", 1, Duration::from_millis(10))
+            .is_ok());
+        assert!(trie
+            .insert_with_ttl("hell", 2, Duration::from_secs(60))
+            .is_ok());
+
+        sleep(Duration::from_millis(20));
+        trie.purge_expired();
+
+        // Get values
+        assert_eq!(trie.trie.get("hello").map(|(value, _)| value), None);
+        assert_eq!(trie.get("hell"), Some(&2));
+    }
+}
@@ -1,2 +1,13 @@
+pub mod bounded_trie;
+pub mod codec;
 pub mod concurrent_trie;
+pub mod error;
+pub mod expiring_trie;
+pub mod pooled_trie;
+pub mod radix_trie;
 pub mod single_threaded_trie;
+pub mod suffix_trie;
+mod trie_core;
+
+#[cfg(test)]
+mod semantics_tests;
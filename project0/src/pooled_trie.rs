@@ -0,0 +1,162 @@
+use std::num::NonZeroU32;
+
+use crate::{error::TrieError, single_threaded_trie::Trie};
+
+/// A `Trie` keeping its values in a separate pool.
+///
+/// Nodes store a 4 byte index into the pool instead of an `Option<T>`, so for large values and
+/// mostly structural nodes (long keys sharing few values) this takes far less memory. Slots of
+/// removed values are reused by later inserts.
+pub struct PooledTrie<T> {
+    trie: Trie<Slot>,
+    values: Vec<Option<T>>,
+    // Slots of `values` that are empty
+    free_slots: Vec<Slot>,
+}
+
+// Index into `values` plus one, so that `Option<Slot>` still takes 4 bytes
+#[derive(Debug, Clone, Copy)]
+struct Slot(NonZeroU32);
+
+impl Slot {
+    fn index(self) -> usize {
+        self.0.get() as usize - 1
+    }
+}
+
+impl<T> Default for PooledTrie<T> {
+    fn default() -> Self {
+        Self {
+            trie: Trie::new(),
+            values: Vec::new(),
+            free_slots: Vec::new(),
+        }
+    }
+}
+
+impl<T> PooledTrie<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.trie.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trie.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&T> {
+        let slot = self.trie.get(key)?;
+        self.values[slot.index()].as_ref()
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut T> {
+        let slot = self.trie.get(key)?;
+        self.values[slot.index()].as_mut()
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the pool would hold more than `u32::MAX` values.
+    pub fn insert(&mut self, key: &str, value: T) -> Result<(), TrieError> {
+        let slot = self.store(value);
+        if let Err(error) = self.trie.insert(key, slot) {
+            self.take(slot);
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Removes the value at `key` and returns it
+    pub fn remove(&mut self, key: &str) -> Result<T, TrieError> {
+        let slot = self.trie.remove_report(key)?.value;
+        Ok(self.take(slot))
+    }
+
+    // Puts `value` into a free slot, growing the pool if there is none
+    fn store(&mut self, value: T) -> Slot {
+        match self.free_slots.pop() {
+            Some(slot) => {
+                self.values[slot.index()] = Some(value);
+                slot
+            }
+            None => {
+                self.values.push(Some(value));
+                let index = u32::try_from(self.values.len()).expect("Value pool is full");
+                Slot(NonZeroU32::new(index).unwrap())
+            }
+        }
+    }
+
+    // Takes the value out of `slot` and frees the slot
+    fn take(&mut self, slot: Slot) -> T {
+        self.free_slots.push(slot);
+        self.values[slot.index()].take().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use super::*;
+    use crate::trie_core::TrieNode;
+
+    #[test]
+    fn insert_get_remove() {
+        // Create new `PooledTrie`
+        let mut trie = PooledTrie::new();
+
+        // Insert values
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("help", 2).is_ok());
+        assert_eq!(trie.insert("hello", 3), Err(TrieError::DuplicateKey));
+        assert_eq!(trie.insert("", 3), Err(TrieError::EmptyKey));
+        assert_eq!(trie.len(), 2);
+
+        // Get values
+        assert_eq!(trie.get("hello"), Some(&1));
+        assert_eq!(trie.get("hel"), None);
+        *trie.get_mut("help").unwrap() += 10;
+        assert_eq!(trie.get("help"), Some(&12));
+
+        // Removed slots are reused
+        assert_eq!(trie.remove("hello"), Ok(1));
+        assert_eq!(trie.remove("hello"), Err(TrieError::KeyNotFound));
+        assert!(trie.insert("world", 4).is_ok());
+        assert!(trie.insert("word", 5).is_ok());
+        assert_eq!(trie.values.len(), 3);
+        assert_eq!(trie.get("world"), Some(&4));
+        assert_eq!(trie.get("word"), Some(&5));
+        assert_eq!(trie.get("help"), Some(&12));
+    }
+
+    #[test]
+    fn uses_less_memory_than_inline_values() {
+        type Value = [u8; 256];
+
+        // Most nodes only spell out the long keys
+        let keys: Vec<_> = (0..100)
+            .map(|i| format!("{:03}/very/long/path/to/a/file", i))
+            .collect();
+
+        // Create new `PooledTrie` and `Trie`
+        let mut pooled = PooledTrie::new();
+        let mut inline = Trie::new();
+        for key in &keys {
+            assert!(pooled.insert(key, [0; 256]).is_ok());
+            assert!(inline.insert(key, [0; 256]).is_ok());
+        }
+
+        assert_eq!(size_of::<Option<Slot>>(), 4);
+        let node_count = inline.node_count();
+        assert_eq!(pooled.trie.node_count(), node_count);
+
+        let inline_bytes = node_count * size_of::<TrieNode<Value>>();
+        let pooled_bytes = node_count * size_of::<TrieNode<Slot>>()
+            + pooled.values.len() * size_of::<Option<Value>>();
+        assert!(pooled_bytes * 2 < inline_bytes);
+    }
+}
@@ -0,0 +1,245 @@
+use std::mem;
+
+use crate::trie_core::{ChildMap, TrieNode};
+
+/// A read-only trie whose edges hold whole fragments of keys instead of single chars.
+///
+/// Every chain of nodes without a value and with only one child is collapsed into one edge, so
+/// it needs far fewer nodes than a `Trie` for long keys sharing few prefixes. Build it with
+/// `Trie::to_radix`.
+pub struct RadixTrie<T> {
+    root: RadixNode<T>,
+    // Number of stored values
+    len: usize,
+}
+
+struct RadixNode<T> {
+    value: Option<T>,
+    // Sorted by fragment, and no two fragments start with the same char
+    children: Vec<(String, RadixNode<T>)>,
+}
+
+// Drops the descendants one at a time, like `TrieNode`
+impl<T> Drop for RadixNode<T> {
+    fn drop(&mut self) {
+        let mut stack: Vec<_> = self.children.drain(..).map(|(_, node)| node).collect();
+        while let Some(mut node) = stack.pop() {
+            stack.extend(node.children.drain(..).map(|(_, node)| node));
+        }
+    }
+}
+
+impl<T> RadixNode<T> {
+    fn new(value: Option<T>, children: Vec<(String, RadixNode<T>)>) -> Self {
+        RadixNode { value, children }
+    }
+
+    // Returns the child whose fragment starts `key`, and the rest of `key` after it
+    fn child<'a>(&self, key: &'a str) -> Option<(&RadixNode<T>, &'a str)> {
+        let first = key.chars().next()?;
+        let index = self
+            .children
+            .binary_search_by_key(&first, |(fragment, _)| fragment.chars().next().unwrap())
+            .ok()?;
+        let (fragment, child) = &self.children[index];
+        Some((child, key.strip_prefix(fragment.as_str())?))
+    }
+}
+
+// A node of the source trie whose children are still being converted
+struct Frame<T> {
+    fragment: String,
+    value: Option<T>,
+    // Popped in ascending order
+    pending: Vec<(char, TrieNode<T>)>,
+    children: Vec<(String, RadixNode<T>)>,
+}
+
+impl<T> Frame<T> {
+    fn new(fragment: String, value: Option<T>, map: ChildMap<T>) -> Self {
+        let mut pending: Vec<_> = map.into_iter().collect();
+        pending.sort_unstable_by(|(a, _), (b, _)| b.cmp(a));
+        Frame {
+            fragment,
+            value,
+            pending,
+            children: Vec::new(),
+        }
+    }
+}
+
+impl<T> RadixTrie<T> {
+    // Converts the nodes of a `Trie` holding `len` values depth first, without recursing
+    pub(crate) fn from_nodes(top_level_nodes: ChildMap<T>, len: usize) -> Self {
+        let mut stack = vec![Frame::new(String::new(), None, top_level_nodes)];
+        loop {
+            let frame = stack.last_mut().unwrap();
+            if let Some((char, mut node)) = frame.pending.pop() {
+                // Follow the chain of valueless single children down to the next node to keep
+                let mut fragment = String::from(char);
+                while !node.is_end() && node.child_nodes.len() == 1 {
+                    let (char, child) = node.child_nodes.drain().next().unwrap();
+                    fragment.push(char);
+                    node = child;
+                }
+                let map = mem::take(&mut node.child_nodes);
+                stack.push(Frame::new(fragment, node.value.take(), map));
+                continue;
+            }
+
+            let frame = stack.pop().unwrap();
+            let node = RadixNode::new(frame.value, frame.children);
+            match stack.last_mut() {
+                Some(parent) => parent.children.push((frame.fragment, node)),
+                None => return RadixTrie { root: node, len },
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &str) -> Option<&T> {
+        let (mut node, mut rest) = self.root.child(key)?;
+        while !rest.is_empty() {
+            (node, rest) = node.child(rest)?;
+        }
+        node.value.as_ref()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns every stored key in lexicographic order
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys = Vec::new();
+        let mut stack = vec![(String::new(), &self.root)];
+        while let Some((key, node)) = stack.pop() {
+            for (fragment, child) in node.children.iter().rev() {
+                stack.push((key.clone() + fragment, child));
+            }
+            if node.value.is_some() {
+                keys.push(key);
+            }
+        }
+        keys
+    }
+
+    /// Returns the fragment of every edge depth first in key order, borrowed from the trie so that
+    /// no `String` is built, e.g. to find which chars keys use.
+    ///
+    /// Each fragment comes right after the one of its parent edge, and joining the fragments on
+    /// the way down to a node gives its key, e.g. `"hel"`, `"p"` and `"ful"` for `"helpful"`.
+    /// Use `keys` when the full keys are needed, as the flat sequence doesn't say where one path
+    /// ends.
+    pub fn fragments(&self) -> impl Iterator<Item = &str> {
+        let mut stack: Vec<_> = self.root.children.iter().rev().collect();
+        std::iter::from_fn(move || {
+            let (fragment, node) = stack.pop()?;
+            stack.extend(node.children.iter().rev());
+            Some(fragment.as_str())
+        })
+    }
+
+    /// Returns the number of nodes, not counting the root
+    pub fn node_count(&self) -> usize {
+        let mut count = 0;
+        let mut stack = vec![&self.root];
+        while let Some(node) = stack.pop() {
+            count += node.children.len();
+            stack.extend(node.children.iter().map(|(_, child)| child));
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::single_threaded_trie::Trie;
+
+    #[test]
+    fn answers_like_the_source_trie() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        let keys = [
+            "hello", "help", "helpful", "world", "wor", "a", "über", "übung",
+        ];
+        for (i, key) in keys.iter().enumerate() {
+            assert!(trie.insert(key, i).is_ok());
+        }
+        let trie_keys: Vec<_> = trie.iter().map(|(key, _)| key).collect();
+        let trie_node_count = trie.node_count();
+        let queries = [
+            "hel", "he", "helpfu", "w", "wo", "worlds", "ü", "üb", "b", "",
+        ];
+        let trie_answers: Vec<_> = queries
+            .iter()
+            .chain(&keys)
+            .map(|key| trie.get(key).copied())
+            .collect();
+
+        // Compress it
+        let radix = trie.to_radix();
+        assert_eq!(radix.len(), keys.len());
+        assert_eq!(radix.keys(), trie_keys);
+        let radix_answers: Vec<_> = queries
+            .iter()
+            .chain(&keys)
+            .map(|key| radix.get(key).copied())
+            .collect();
+        assert_eq!(radix_answers, trie_answers);
+
+        // a, hel-lo, hel-p-ful, wor-ld, üb-er, üb-ung
+        assert_eq!(radix.node_count(), 10);
+        assert!(radix.node_count() < trie_node_count);
+    }
+
+    #[test]
+    fn very_long_keys() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        let long_key = "a".repeat(100_000);
+        assert!(trie.insert(&long_key, 1).is_ok());
+        assert!(trie.insert(&(long_key.clone() + "b"), 2).is_ok());
+
+        let radix = trie.to_radix();
+        assert_eq!(radix.node_count(), 2);
+        assert_eq!(radix.get(&long_key), Some(&1));
+        assert_eq!(radix.get(&long_key[1..]), None);
+        assert_eq!(radix.keys().len(), 2);
+    }
+
+    #[test]
+    fn fragments_are_the_edges() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        let keys = [
+            "hello", "help", "helpful", "world", "wor", "a", "über", "übung",
+        ];
+        for (i, key) in keys.iter().enumerate() {
+            assert!(trie.insert(key, i).is_ok());
+        }
+
+        // a, hel-lo, hel-p-ful, wor-ld, üb-er, üb-ung
+        let radix = trie.to_radix();
+        let fragments: Vec<&str> = radix.fragments().collect();
+        assert_eq!(
+            fragments,
+            ["a", "hel", "lo", "p", "ful", "wor", "ld", "üb", "er", "ung"]
+        );
+        assert_eq!(fragments.len(), radix.node_count());
+        assert_eq!(Trie::<()>::new().to_radix().fragments().next(), None);
+    }
+}
@@ -0,0 +1,190 @@
+// Scenarios run against both tries, so the single-threaded and concurrent wrappers around
+// `RawTrie` can't drift apart.
+
+use crate::{concurrent_trie, error::TrieError, single_threaded_trie};
+
+// The operations both tries share, with the concurrent one's guards unwrapped
+trait CommonTrie {
+    fn insert(&mut self, key: &str, value: i32) -> Result<(), TrieError>;
+    fn get(&self, key: &str) -> Option<i32>;
+    fn remove(&mut self, key: &str) -> Result<(), TrieError>;
+    fn remove_if(&mut self, key: &str, pred: fn(&i32) -> bool) -> bool;
+    fn remove_many(&mut self, keys: &[&str]) -> usize;
+    fn swap_value(&mut self, key: &str, value: i32) -> Option<i32>;
+    fn len(&self) -> usize;
+    fn debug(&self) -> String;
+}
+
+impl CommonTrie for single_threaded_trie::Trie<i32> {
+    fn insert(&mut self, key: &str, value: i32) -> Result<(), TrieError> {
+        single_threaded_trie::Trie::insert(self, key, value)
+    }
+
+    fn get(&self, key: &str) -> Option<i32> {
+        single_threaded_trie::Trie::get(self, key).copied()
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), TrieError> {
+        single_threaded_trie::Trie::remove(self, key)
+    }
+
+    fn remove_if(&mut self, key: &str, pred: fn(&i32) -> bool) -> bool {
+        single_threaded_trie::Trie::remove_if(self, key, pred)
+    }
+
+    fn remove_many(&mut self, keys: &[&str]) -> usize {
+        single_threaded_trie::Trie::remove_many(self, keys)
+    }
+
+    fn swap_value(&mut self, key: &str, value: i32) -> Option<i32> {
+        single_threaded_trie::Trie::swap_value(self, key, value)
+    }
+
+    fn len(&self) -> usize {
+        single_threaded_trie::Trie::len(self)
+    }
+
+    fn debug(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+impl CommonTrie for concurrent_trie::Trie<i32> {
+    fn insert(&mut self, key: &str, value: i32) -> Result<(), TrieError> {
+        concurrent_trie::Trie::insert(self, key, value)
+    }
+
+    fn get(&self, key: &str) -> Option<i32> {
+        *concurrent_trie::Trie::get(self, key)
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), TrieError> {
+        concurrent_trie::Trie::remove(self, key)
+    }
+
+    fn remove_if(&mut self, key: &str, pred: fn(&i32) -> bool) -> bool {
+        concurrent_trie::Trie::remove_if(self, key, pred)
+    }
+
+    fn remove_many(&mut self, keys: &[&str]) -> usize {
+        concurrent_trie::Trie::remove_many(self, keys)
+    }
+
+    fn swap_value(&mut self, key: &str, value: i32) -> Option<i32> {
+        concurrent_trie::Trie::swap_value(self, key, value)
+    }
+
+    fn len(&self) -> usize {
+        concurrent_trie::Trie::len(self)
+    }
+
+    fn debug(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+// Runs `scenario` on a new trie of each kind and checks they end up formatted the same
+fn check_both(scenario: impl Fn(&mut dyn CommonTrie)) {
+    let mut single = single_threaded_trie::Trie::new();
+    let mut concurrent = concurrent_trie::Trie::new();
+    scenario(&mut single);
+    scenario(&mut concurrent);
+    assert_eq!(single.debug(), concurrent.debug());
+}
+
+#[test]
+fn insert_and_get() {
+    check_both(|trie| {
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("hell", 2).is_ok());
+        assert_eq!(trie.insert("hello", 3), Err(TrieError::DuplicateKey));
+        assert_eq!(trie.insert("", 4), Err(TrieError::EmptyKey));
+
+        assert_eq!(trie.get("hello"), Some(1));
+        assert_eq!(trie.get("hell"), Some(2));
+        assert_eq!(trie.get("he"), None);
+        assert_eq!(trie.get(""), None);
+    });
+}
+
+#[test]
+fn remove_prunes_only_unused_nodes() {
+    check_both(|trie| {
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("help", 2).is_ok());
+        assert!(trie.insert("he", 3).is_ok());
+
+        assert!(trie.remove("hello").is_ok());
+        assert_eq!(trie.remove("hello"), Err(TrieError::KeyNotFound));
+        assert_eq!(trie.remove("hel"), Err(TrieError::KeyNotFound));
+        assert_eq!(trie.remove(""), Err(TrieError::EmptyKey));
+        assert!(trie.remove("he").is_ok());
+
+        assert_eq!(trie.get("help"), Some(2));
+    });
+}
+
+#[test]
+fn bulk_and_conditional_updates() {
+    check_both(|trie| {
+        assert!(trie.insert("a", 1).is_ok());
+        assert!(trie.insert("ab", 2).is_ok());
+        assert!(trie.insert("abc", 3).is_ok());
+
+        assert!(!trie.remove_if("ab", |value| *value > 2));
+        assert!(trie.remove_if("abc", |value| *value > 2));
+        assert_eq!(trie.remove_many(&["a", "abc", "x"]), 1);
+        assert_eq!(trie.swap_value("ab", 4), Some(2));
+        assert_eq!(trie.swap_value("b", 5), None);
+
+        assert_eq!(trie.get("ab"), Some(4));
+        assert_eq!(trie.get("b"), Some(5));
+    });
+}
+
+#[test]
+fn remove_of_structural_key_fails() {
+    check_both(|trie| {
+        assert!(trie.insert("hello", 1).is_ok());
+        let before = trie.debug();
+
+        // Only nodes lead through these keys, none holds a value
+        for key in ["h", "hel", "hell"] {
+            assert_eq!(trie.remove(key), Err(TrieError::KeyNotFound));
+            assert!(!trie.remove_if(key, |_| true));
+        }
+        assert_eq!(trie.remove("hello!"), Err(TrieError::KeyNotFound));
+        assert_eq!(trie.debug(), before);
+        assert_eq!(trie.get("hello"), Some(1));
+    });
+}
+
+#[test]
+fn remove_decrements_len_once() {
+    check_both(|trie| {
+        assert!(trie.insert("a", 1).is_ok());
+        assert!(trie.insert("ab", 2).is_ok());
+        assert!(trie.insert("abc", 3).is_ok());
+        assert!(trie.insert("abd", 4).is_ok());
+        assert_eq!(trie.len(), 4);
+
+        // The node at "ab" keeps its children
+        assert!(trie.remove("ab").is_ok());
+        assert_eq!(trie.len(), 3);
+        assert_eq!(trie.get("abc"), Some(3));
+        assert_eq!(trie.get("abd"), Some(4));
+
+        // Failed removes leave `len` alone
+        assert_eq!(trie.remove("ab"), Err(TrieError::KeyNotFound));
+        assert_eq!(trie.remove(""), Err(TrieError::EmptyKey));
+        assert_eq!(trie.remove("abcd"), Err(TrieError::KeyNotFound));
+        assert_eq!(trie.len(), 3);
+
+        // A leaf, then a key whose node only keeps a structural child
+        assert!(trie.remove("abc").is_ok());
+        assert!(trie.remove("a").is_ok());
+        assert_eq!(trie.len(), 1);
+        assert!(trie.remove("abd").is_ok());
+        assert_eq!(trie.len(), 0);
+    });
+}
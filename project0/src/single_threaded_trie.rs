@@ -1,17 +1,352 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
-    ptr::null_mut,
+    any::Any,
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{hash_map, HashMap, HashSet, VecDeque},
+    fmt,
+    hash::{Hash, Hasher},
+    io::{self, BufRead, Read, Write},
+    iter::Sum,
+    ops::ControlFlow,
+    rc::Rc,
+};
+
+pub use crate::trie_core::DuplicatePolicy;
+use crate::{
+    codec::{self, Decode, Encode},
+    error::TrieError,
+    radix_trie::RadixTrie,
+    trie_core::{retain_nodes, ChildMap, NodePath, RawTrie, SortedNodes, TrieNode},
 };
 
-#[derive(Debug)]
 pub struct Trie<T> {
-    top_level_nodes: HashMap<char, TrieNode<T>>,
+    raw: RawTrie<T>,
+    // Number of stored values
+    len: usize,
+    policy: DuplicatePolicy,
+    max_key_len: Option<usize>,
+    max_fanout: Option<usize>,
+    auto_shrink: bool,
+    terminal_only: bool,
+    // Sequence number given to the next key that gets a value
+    #[cfg(feature = "insertion-order")]
+    next_sequence: u64,
+    #[cfg(feature = "access-tracking")]
+    track_access: bool,
+    #[cfg(feature = "unicode-normalization")]
+    normalization: Normalization,
+}
+
+/// Unicode normalization applied to keys before they are stored or looked up
+#[cfg(feature = "unicode-normalization")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Normalization {
+    /// Keys are used as given, so canonically equivalent strings are different keys
+    #[default]
+    None,
+    /// Keys are converted to Normalization Form C (composed)
+    Nfc,
+    /// Keys are converted to Normalization Form D (decomposed)
+    Nfd,
+}
+
+/// A compiled pattern for `Trie::keys_matching`, built with e.g. `Regex::new("^h.l+o$")`
+#[cfg(feature = "regex")]
+pub type Regex = regex_automata::dfa::dense::DFA<Vec<u32>>;
+
+/// Node statistics for one depth level of a `Trie`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelStat {
+    /// Length (in chars) of the keys that end at this level
+    pub depth: usize,
+    pub node_count: usize,
+    /// Number of nodes holding a value
+    pub value_count: usize,
+    /// Average number of children per node
+    pub average_fanout: f64,
+}
+
+/// The shape of the trie at a key, returned by `Trie::get_with_meta`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeMeta {
+    /// Number of chars that can follow the key in longer keys
+    pub child_count: usize,
+    /// Whether longer keys split into more than one continuation here
+    pub is_branch: bool,
+}
+
+/// What one walk to a key found, returned by `Trie::probe`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Probe {
+    /// Whether the key has a value
+    pub is_key: bool,
+    /// Whether longer stored keys start with the key
+    pub is_prefix: bool,
+}
+
+/// The differences between two tries found by `Trie::diff`, each in lexicographic key order
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrieDiff<'a, T> {
+    /// Keys only in the other trie, with their values there
+    pub added: Vec<(String, &'a T)>,
+    /// Keys only in this trie, with their values here
+    pub removed: Vec<(String, &'a T)>,
+    /// Keys in both tries with different values, with the value here and then the one there
+    pub changed: Vec<(String, &'a T, &'a T)>,
+}
+
+/// One change of a `TriePatch`
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatchOp<T> {
+    /// Inserts like `Trie::insert`
+    Insert(String, T),
+    /// Removes a stored key
+    Remove(String),
+    /// Replaces the value of a stored key
+    Update(String, T),
+}
+
+/// Changes applied in order by `Trie::apply`
+pub type TriePatch<T> = Vec<PatchOp<T>>;
+
+impl<T: Clone> TrieDiff<'_, T> {
+    /// Returns the patch that turns the first trie of the diff into the second one
+    pub fn to_patch(&self) -> TriePatch<T> {
+        let removed = self
+            .removed
+            .iter()
+            .map(|(key, _)| PatchOp::Remove(key.clone()));
+        let changed = self
+            .changed
+            .iter()
+            .map(|(key, _, value)| PatchOp::Update(key.clone(), (*value).clone()));
+        let added = self
+            .added
+            .iter()
+            .map(|(key, value)| PatchOp::Insert(key.clone(), (*value).clone()));
+        removed.chain(changed).chain(added).collect()
+    }
+}
+
+/// What `Trie::remove_report` removed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoveReport<T> {
+    pub value: T,
+    /// Number of nodes that were left without a value or children, and dropped
+    pub pruned_nodes: usize,
+}
+
+/// Callbacks for `Trie::walk`
+pub trait TrieVisitor<T> {
+    /// Called when the walk reaches the node at `partial_key`, before any of its children
+    fn enter(&mut self, partial_key: &str, value: Option<&T>);
+
+    /// Called after every descendant of the node at `partial_key` has been visited
+    fn leave(&mut self, _partial_key: &str) {}
+}
+
+/// A view into a single key of a `Trie`, returned by `Trie::entry`
+pub enum Entry<'a, T> {
+    Occupied(OccupiedEntry<'a, T>),
+    Vacant(VacantEntry<'a, T>),
+}
+
+/// An entry whose key has a value
+pub struct OccupiedEntry<'a, T> {
+    node: &'a mut TrieNode<T>,
+}
+
+/// An entry whose key has no value.
+///
+/// It keeps the position where the walk from the root stopped, so inserting only creates the
+/// missing nodes and hashes each of their chars once.
+pub struct VacantEntry<'a, T> {
+    position: VacantPosition<'a, T>,
+    // The value counts of the nodes passed on the way
+    ancestor_counts: Vec<&'a mut usize>,
+    child_capacity: usize,
+    len: &'a mut usize,
+    #[cfg(feature = "insertion-order")]
+    next_sequence: &'a mut u64,
+}
+
+enum VacantPosition<'a, T> {
+    // The node for the key exists, but has no value
+    Node(&'a mut TrieNode<T>),
+    // The first missing node, and the chars of the key below it
+    Missing {
+        entry: hash_map::VacantEntry<'a, char, TrieNode<T>>,
+        suffix: String,
+    },
+}
+
+impl<'a, T> Entry<'a, T> {
+    /// Returns the value, first inserting `value` if there is none
+    pub fn or_insert(self, value: T) -> &'a mut T {
+        self.or_insert_with(|| value)
+    }
+
+    /// Returns the value, first inserting the one returned by `f` if there is none
+    pub fn or_insert_with(self, f: impl FnOnce() -> T) -> &'a mut T {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(f()),
+        }
+    }
+
+    /// Returns the value, first inserting `T::default()` if there is none
+    pub fn or_default(self) -> &'a mut T
+    where
+        T: Default,
+    {
+        self.or_insert_with(T::default)
+    }
+
+    /// Applies `f` to the value if there is one
+    pub fn and_modify(mut self, f: impl FnOnce(&mut T)) -> Self {
+        if let Entry::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, T> OccupiedEntry<'a, T> {
+    pub fn get(&self) -> &T {
+        self.node.value.as_ref().unwrap()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.node.value.as_mut().unwrap()
+    }
+
+    pub fn into_mut(self) -> &'a mut T {
+        self.node.value.as_mut().unwrap()
+    }
+
+    /// Replaces the value and returns the old one
+    pub fn insert(&mut self, value: T) -> T {
+        std::mem::replace(self.get_mut(), value)
+    }
+}
+
+impl<'a, T> VacantEntry<'a, T> {
+    /// Stores `value` at the entry's key and returns it
+    pub fn insert(self, value: T) -> &'a mut T {
+        let path = match self.position {
+            VacantPosition::Node(node) => NodePath::new(self.ancestor_counts, node),
+            VacantPosition::Missing { entry, suffix } => entry
+                .insert(TrieNode::with_child_capacity(self.child_capacity))
+                .path_or_insert(suffix.chars(), self.ancestor_counts, self.child_capacity),
+        };
+
+        *self.len += 1;
+        #[cfg(feature = "insertion-order")]
+        {
+            path.node.sequence = *self.next_sequence;
+            *self.next_sequence += 1;
+        }
+        path.get_or_insert_with(|| value)
+    }
+}
+
+/// Inserts keys one after another, only walking the part of each key that differs from the
+/// previous one, e.g. for sorted file paths.
+///
+/// The nodes along the previous key are detached from the trie while the cursor is used, and
+/// put back when it is dropped.
+pub struct InsertCursor<'a, T> {
+    trie: &'a mut Trie<T>,
+    // The nodes along the previous key, each taken out of the children of the one before
+    path: Vec<(char, TrieNode<T>)>,
+}
+
+impl<T> InsertCursor<'_, T> {
+    /// Inserts like `Trie::insert`
+    pub fn insert(&mut self, key: &str, value: T) -> Result<(), TrieError> {
+        let key = self.trie.check_key(key)?;
+
+        let shared_len = self
+            .path
+            .iter()
+            .zip(key.chars())
+            .take_while(|((path_key, _), key)| path_key == key)
+            .count();
+        self.retreat_to(shared_len);
+        let map = match self.path.last() {
+            Some((_, node)) => &node.child_nodes,
+            None => &self.trie.raw.top_level_nodes,
+        };
+        let rest_start = key
+            .char_indices()
+            .nth(shared_len)
+            .map_or(key.len(), |(index, _)| index);
+        self.trie
+            .check_new_path(&self.path, map, &key[rest_start..])?;
+        for key in key.chars().skip(shared_len) {
+            let map = match self.path.last_mut() {
+                Some((_, node)) => &mut node.child_nodes,
+                None => &mut self.trie.raw.top_level_nodes,
+            };
+            let child_capacity = self.trie.raw.child_capacity;
+            let node = map
+                .remove(&key)
+                .unwrap_or_else(|| TrieNode::with_child_capacity(child_capacity));
+            self.path.push((key, node));
+        }
+
+        let (_, node) = self.path.last_mut().unwrap();
+        let is_new = !node.is_end();
+        node.insert(value, self.trie.policy)?;
+        if is_new {
+            self.trie.len += 1;
+            #[cfg(feature = "insertion-order")]
+            {
+                node.sequence = self.trie.next_sequence;
+                self.trie.next_sequence += 1;
+            }
+            for (_, node) in &mut self.path {
+                node.subtree_value_count += 1;
+            }
+        }
+        Ok(())
+    }
+
+    // Puts the nodes deeper than `depth` back into their parents
+    fn retreat_to(&mut self, depth: usize) {
+        while self.path.len() > depth {
+            let (key, node) = self.path.pop().unwrap();
+            let map = match self.path.last_mut() {
+                Some((_, parent)) => &mut parent.child_nodes,
+                None => &mut self.trie.raw.top_level_nodes,
+            };
+            map.insert(key, node);
+        }
+    }
+}
+
+impl<T> Drop for InsertCursor<'_, T> {
+    fn drop(&mut self) {
+        self.retreat_to(0);
+    }
 }
 
 impl<T> Default for Trie<T> {
     fn default() -> Self {
         Self {
-            top_level_nodes: HashMap::new(),
+            raw: RawTrie::default(),
+            len: 0,
+            policy: DuplicatePolicy::default(),
+            max_key_len: None,
+            max_fanout: None,
+            auto_shrink: false,
+            terminal_only: false,
+            #[cfg(feature = "insertion-order")]
+            next_sequence: 0,
+            #[cfg(feature = "access-tracking")]
+            track_access: false,
+            #[cfg(feature = "unicode-normalization")]
+            normalization: Normalization::default(),
         }
     }
 }
@@ -21,234 +356,4216 @@ impl<T> Trie<T> {
         Self::default()
     }
 
-    pub fn get(&self, key: &str) -> Option<&T> {
-        let key_iter = key.chars();
-        let mut map = &self.top_level_nodes;
-        let mut output = None;
-        for key in key_iter {
-            if let Some(node) = map.get(&key) {
-                map = &node.child_nodes;
-                output = node.value.as_ref();
-            } else {
-                return None;
-            }
+    /// Creates a trie with room for `top_level` distinct first chars without reallocating, e.g.
+    /// 26 for lowercase words
+    pub fn with_capacity(top_level: usize) -> Self {
+        Self {
+            raw: RawTrie::with_capacity(top_level),
+            ..Self::default()
         }
-        output
     }
 
-    pub fn insert(&mut self, key: &str, value: T) -> Result<(), &'static str> {
-        if key.is_empty() {
-            return Err("Key can not be empty");
-        }
-        let mut key_iter = key.chars();
-        let first_key = key_iter.next().unwrap();
-        //
+    /// Sets what `insert` does with keys that already have a value, e.g.
+    /// `Trie::new().with_policy(DuplicatePolicy::Replace)`
+    pub fn with_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Makes `insert`, and every other method that can store a key, reject keys with more than
+    /// `max` chars with `TrieError::KeyTooLong`, before allocating any nodes. Keys are unbounded
+    /// by default.
+    pub fn with_max_key_len(mut self, max: usize) -> Self {
+        self.max_key_len = Some(max);
+        self
+    }
+
+    /// Makes `insert`, and every other method that can store a key, return
+    /// `TrieError::FanoutExceeded` for keys that would give a node (or the top level) more than
+    /// `max` children, so that adversarial keys can't build huge child maps. Fanout is unbounded
+    /// by default.
+    pub fn with_max_fanout(mut self, max: usize) -> Self {
+        self.max_fanout = Some(max);
+        self
+    }
+
+    /// Makes every node created afterwards start with room for `hint` children, so bulk loads of
+    /// high fanout keys don't grow the child maps one rehash at a time. No hint (0) by default.
+    pub fn with_children_capacity_hint(mut self, hint: usize) -> Self {
+        self.raw.child_capacity = hint;
+        self
+    }
 
-        if let Entry::Vacant(e) = self.top_level_nodes.entry(first_key) {
-            let new_trie = TrieNode::new_top_level_node();
-            e.insert(new_trie);
+    /// When `true`, `remove_many` and `retain` shrink the child maps of nodes that lost at least
+    /// half of their children, so bulk deletes don't leave oversized allocations behind.
+    /// Off by default.
+    pub fn with_auto_shrink(mut self, auto_shrink: bool) -> Self {
+        self.auto_shrink = auto_shrink;
+        self
+    }
+
+    /// When `true`, `insert` and every other method that can store a key keep any stored key
+    /// from being a prefix of another, as in phone number or prefix code dictionaries, and
+    /// return `TrieError::PrefixConflict` for keys that would break that. Off by default.
+    pub fn with_terminal_only(mut self, terminal_only: bool) -> Self {
+        self.terminal_only = terminal_only;
+        self
+    }
+
+    /// When `true`, every `get` or `contains_key` that finds a value counts towards the key's
+    /// `access_count`. Off by default.
+    #[cfg(feature = "access-tracking")]
+    pub fn with_access_tracking(mut self, track_access: bool) -> Self {
+        self.track_access = track_access;
+        self
+    }
+
+    /// Sets the normalization applied to every key, so that e.g. `"\u{e9}"` and `"e\u{301}"`
+    /// are the same key. Keys are not normalized by default.
+    ///
+    /// Changing it only affects keys used afterwards, so set it before inserting.
+    #[cfg(feature = "unicode-normalization")]
+    pub fn with_normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Returns the value stored at exactly `key`, even if longer keys continue past it
+    pub fn get(&self, key: &str) -> Option<&T> {
+        let node = self.get_node(key)?;
+        #[cfg(feature = "access-tracking")]
+        if self.track_access {
+            node.record_access();
         }
-        let first_trie_node = self.top_level_nodes.get_mut(&first_key).unwrap();
+        node.value.as_ref()
+    }
 
-        first_trie_node.insert(key_iter, value)
+    /// Returns how often `get` or `contains_key` found the value at `key` while access tracking
+    /// was on, or `None` if there is no value.
+    ///
+    /// The count belongs to the key's node, so a key that is removed and inserted again keeps
+    /// its count while longer keys still pass through it.
+    #[cfg(feature = "access-tracking")]
+    pub fn access_count(&self, key: &str) -> Option<u64> {
+        self.get_node(key)?.access_count()
     }
 
-    pub fn remove(&mut self, key: &str) -> Result<(), &'static str> {
-        // ------------------------------------------------
-        // Get node
-        if key.is_empty() {
-            return Err("Key can not be empty");
+    /// Like `get`, but also returns the depth in chars of the key's node, which differs from the
+    /// length of `key` when normalization changes it
+    pub fn get_at_depth(&self, key: &str) -> Option<(&T, usize)> {
+        let key = self.normalize(key);
+        let value = self.raw.get_node(&key)?.value.as_ref()?;
+        Some((value, key.chars().count()))
+    }
+
+    /// Like `get`, but also describes the node at `key`, e.g. so an autocomplete can tell
+    /// whether longer keys continue past it
+    pub fn get_with_meta(&self, key: &str) -> Option<(&T, NodeMeta)> {
+        let node = self.get_node(key)?;
+        let child_count = node.child_nodes.len();
+        let meta = NodeMeta {
+            child_count,
+            is_branch: child_count > 1,
+        };
+        Some((node.value.as_ref()?, meta))
+    }
+
+    /// Returns the value of the node reached by walking `path`.
+    ///
+    /// This is the same as `get`: interior nodes that hold a value (`"hel"` when both `"hel"`
+    /// and `"hello"` are stored) return it, and purely structural nodes (`"he"`) return `None`.
+    pub fn value_at(&self, path: &str) -> Option<&T> {
+        self.get(path)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut T> {
+        self.get_node_mut(key)?.value.as_mut()
+    }
+
+    /// Returns mutable references to the values at `a` and `b` at the same time, e.g. to swap
+    /// or transfer between two entries.
+    ///
+    /// If `a` and `b` are the same key its value is only returned in the first slot.
+    pub fn get_pair_mut(&mut self, a: &str, b: &str) -> (Option<&mut T>, Option<&mut T>) {
+        let (a, b) = (self.normalize(a), self.normalize(b));
+        if a == b {
+            return (self.get_mut(&a), None);
         }
 
-        let mut key_iter = key.chars();
-        let first_key = key_iter.next().unwrap();
+        // Below the node at the longest common prefix the two paths are disjoint
+        let common_len = a.chars().zip(b.chars()).take_while(|(a, b)| a == b).count();
+        let split_at = |key: &str| {
+            key.char_indices()
+                .nth(common_len)
+                .map_or(key.len(), |(i, _)| i)
+        };
+        let (a_rest, b_rest) = (&a[split_at(&a)..], &b[split_at(&b)..]);
 
-        let mut current_node = if let Some(trie_node) = self.top_level_nodes.get_mut(&first_key) {
-            trie_node
+        let child_nodes = if common_len == 0 {
+            &mut self.raw.top_level_nodes
         } else {
-            return Err("No corresponding value");
+            let Some(node) = self.get_node_mut(&a[..split_at(&a)]) else {
+                return (None, None);
+            };
+            if a_rest.is_empty() {
+                return (
+                    node.value.as_mut(),
+                    get_value_mut(&mut node.child_nodes, b_rest),
+                );
+            }
+            if b_rest.is_empty() {
+                return (
+                    get_value_mut(&mut node.child_nodes, a_rest),
+                    node.value.as_mut(),
+                );
+            }
+            &mut node.child_nodes
         };
+        // One of the keys is empty, which never has a value
+        if a_rest.is_empty() {
+            return (None, get_value_mut(child_nodes, b_rest));
+        }
+        if b_rest.is_empty() {
+            return (get_value_mut(child_nodes, a_rest), None);
+        }
 
-        // Last parent of the node with only one child not containing any value (or matching node)
-        let mut parent_node_ptr_and_key = Some((current_node.parent_node_ptr, first_key));
+        let (mut a_iter, mut b_iter) = (a_rest.chars(), b_rest.chars());
+        let [a_node, b_node] =
+            child_nodes.get_disjoint_mut([&a_iter.next().unwrap(), &b_iter.next().unwrap()]);
+        (
+            a_node
+                .and_then(|node| node.get_descendant_mut(a_iter))
+                .and_then(|node| node.value.as_mut()),
+            b_node
+                .and_then(|node| node.get_descendant_mut(b_iter))
+                .and_then(|node| node.value.as_mut()),
+        )
+    }
 
-        for key in key_iter {
-            current_node = if let Some(node) = current_node.get_node_mut(&key) {
-                node
-            } else {
-                return Err("No corresponding value");
-            };
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
 
-            if (current_node.is_end() && !current_node.child_nodes.is_empty())
-                || current_node.child_nodes.len() > 1
-            {
-                parent_node_ptr_and_key = None;
-            } else if parent_node_ptr_and_key.is_none() {
-                parent_node_ptr_and_key = Some((current_node.parent_node_ptr, key));
-            }
+    /// Returns whether any stored key starts with `prefix`
+    pub fn starts_with(&self, prefix: &str) -> bool {
+        if prefix.is_empty() {
+            return !self.is_empty();
         }
+        self.get_node(prefix).is_some_and(TrieNode::has_value)
+    }
 
-        // ------------------------------------------------
-        // Removing
+    /// Tells whether `key` is a stored key and whether it is a prefix of longer ones, in one walk
+    /// instead of `contains_key` and `starts_with` walking it twice
+    pub fn probe(&self, key: &str) -> Probe {
+        if key.is_empty() {
+            return Probe {
+                is_key: false,
+                is_prefix: !self.is_empty(),
+            };
+        }
+        match self.get_node(key) {
+            Some(node) => Probe {
+                is_key: node.is_end(),
+                // Stops at the first value, which is the first node visited unless nodes
+                // without values were kept
+                is_prefix: node.child_nodes.values().any(TrieNode::has_value),
+            },
+            None => Probe::default(),
+        }
+    }
 
-        if !current_node.is_end() {
-            return Err("No corresponding value");
+    /// Returns how many stored keys start with `prefix`.
+    ///
+    /// Every node keeps the number of values below it up to date, so this only walks `prefix`.
+    pub fn count_prefix(&self, prefix: &str) -> usize {
+        if prefix.is_empty() {
+            return self.len;
         }
+        self.get_node(prefix)
+            .map_or(0, |node| node.subtree_value_count)
+    }
 
-        if !current_node.child_nodes.is_empty() {
-            current_node.set_value(None);
-            return Ok(());
+    /// Returns every stored key starting with `prefix`, in lexicographic order
+    pub fn keys_with_prefix(&self, prefix: &str) -> Vec<String> {
+        self.iter_prefix(prefix).map(|(key, _)| key).collect()
+    }
+
+    /// Returns what follows `prefix` in each key starting with it, in lexicographic order, e.g.
+    /// the part of a completion the user hasn't typed yet
+    pub fn suffixes_of(&self, prefix: &str) -> Vec<String> {
+        let prefix_len = self.normalize(prefix).len();
+        self.iter_prefix(prefix)
+            .map(|(mut key, _)| {
+                key.drain(..prefix_len);
+                key
+            })
+            .collect()
+    }
+
+    /// Returns the keys starting with `prefix` and their values, in lexicographic key order.
+    ///
+    /// Nodes are only visited as the iterator advances, so taking the first few results is
+    /// cheap even below a large subtree.
+    pub fn iter_prefix(&self, prefix: &str) -> PrefixIter<'_, T> {
+        let prefix = &*self.normalize(prefix);
+        let mut stack = Vec::new();
+        if prefix.is_empty() {
+            push_sorted_children(&mut stack, prefix, &self.raw.top_level_nodes);
+        } else if let Some(node) = self.get_node(prefix) {
+            stack.push((prefix.to_owned(), node));
         }
+        Iter { stack }
+    }
 
-        if parent_node_ptr_and_key.is_none() {
-            return Ok(());
+    /// Calls `f` with every key starting with `prefix` and its value, in lexicographic key order,
+    /// so the values can be updated in place, e.g. to decay the counters in one namespace
+    pub fn for_each_prefix_mut(&mut self, prefix: &str, mut f: impl FnMut(&str, &mut T)) {
+        let prefix = self.normalize(prefix).into_owned();
+        let mut stack = Vec::new();
+        if prefix.is_empty() {
+            push_sorted_children_mut(&mut stack, &prefix, &mut self.raw.top_level_nodes);
+        } else if let Some(node) = self.raw.get_node_mut(&prefix) {
+            stack.push((prefix, node));
         }
 
-        // SAFETY:
-        unsafe {
-            let parent_node_ptr_and_key = parent_node_ptr_and_key.unwrap();
-            if parent_node_ptr_and_key.0.is_null() {
-                let _ = self.top_level_nodes.remove(&parent_node_ptr_and_key.1);
-            } else {
-                let parent_node = &mut *parent_node_ptr_and_key.0;
-                let _ = parent_node.child_nodes.remove(&parent_node_ptr_and_key.1);
+        while let Some((key, node)) = stack.pop() {
+            if let Some(value) = &mut node.value {
+                f(&key, value);
             }
+            push_sorted_children_mut(&mut stack, &key, &mut node.child_nodes);
         }
-
-        Ok(())
     }
-}
 
-#[derive(Debug)]
-struct TrieNode<T> {
-    value: Option<T>,
-    child_nodes: HashMap<char, TrieNode<T>>,
-    parent_node_ptr: *mut TrieNode<T>,
-}
+    /// Folds `f` over the keys starting with `prefix` and their values, in lexicographic key
+    /// order, e.g. to sum the counters in one namespace
+    pub fn fold_prefix<A>(&self, prefix: &str, init: A, mut f: impl FnMut(A, &str, &T) -> A) -> A {
+        self.iter_prefix(prefix)
+            .fold(init, |acc, (key, value)| f(acc, &key, value))
+    }
 
-impl<T> TrieNode<T> {
-    fn is_end(&self) -> bool {
-        self.value.is_some()
+    /// Calls `f` with the keys starting with `prefix` and their values, in lexicographic key
+    /// order, until it breaks, e.g. to find the first completion satisfying some condition
+    pub fn try_for_each_prefix<B>(
+        &self,
+        prefix: &str,
+        mut f: impl FnMut(&str, &T) -> ControlFlow<B>,
+    ) -> ControlFlow<B> {
+        self.iter_prefix(prefix)
+            .try_for_each(|(key, value)| f(&key, value))
     }
 
-    fn new(parent_node_ptr: *mut TrieNode<T>) -> Self {
-        TrieNode {
-            value: None,
-            child_nodes: HashMap::new(),
-            parent_node_ptr,
+    /// Returns the keys matching `pattern` anywhere, like `regex::Regex::is_match`, in
+    /// lexicographic order.
+    ///
+    /// The pattern is run along the trie, so subtrees it can't match in are skipped: patterns
+    /// anchored with `^` only visit the nodes of keys they could still match.
+    #[cfg(feature = "regex")]
+    pub fn keys_matching(&self, pattern: &Regex) -> Vec<String> {
+        use regex_automata::{dfa::Automaton, util::primitives::StateID, Input};
+
+        enum Step {
+            // A prefix of the key matches, so every key below does
+            Matched,
+            Live(StateID),
+            // The pattern can't match anymore
+            Dead,
+        }
+
+        // Runs the pattern over `key`. Matches are reported one byte late, so they are checked
+        // after every byte, before a dead state would prune the subtree.
+        let advance = |mut state: StateID, key: char| {
+            let mut buf = [0; 4];
+            for byte in key.encode_utf8(&mut buf).bytes() {
+                state = pattern.next_state(state, byte);
+                if pattern.is_match_state(state) {
+                    return Step::Matched;
+                }
+                if pattern.is_dead_state(state) || pattern.is_quit_state(state) {
+                    return Step::Dead;
+                }
+            }
+            Step::Live(state)
+        };
+
+        let start = pattern
+            .start_state_forward(&Input::new(""))
+            .expect("Patterns always have a start state for the beginning of a key");
+        let mut keys = Vec::new();
+        // Nodes still to visit, with the pattern state after their parent's key, or `None` once
+        // a prefix of the key matches
+        let mut stack: Vec<_> = sorted_children(&self.raw.top_level_nodes)
+            .into_iter()
+            .rev()
+            .map(|(char, node)| (char.to_string(), node, Some(start)))
+            .collect();
+        while let Some((key, node, parent_state)) = stack.pop() {
+            let state = match parent_state {
+                Some(state) => match advance(state, key.chars().next_back().unwrap()) {
+                    Step::Matched => None,
+                    Step::Live(state) => Some(state),
+                    Step::Dead => continue,
+                },
+                None => None,
+            };
+            let matches =
+                state.is_none_or(|state| pattern.is_match_state(pattern.next_eoi_state(state)));
+            if node.is_end() && matches {
+                keys.push(key.clone());
+            }
+            for (char, child) in sorted_children(&node.child_nodes).into_iter().rev() {
+                stack.push((format!("{}{}", key, char), child, state));
+            }
         }
+        keys
     }
 
-    fn new_top_level_node() -> Self {
-        TrieNode {
-            value: None,
-            child_nodes: HashMap::new(),
-            parent_node_ptr: null_mut(),
+    /// Returns a view of the keys starting with `prefix`, or `None` if no node spells `prefix`
+    pub fn subtrie(&self, prefix: &str) -> Option<SubTrie<'_, T>> {
+        if prefix.is_empty() {
+            return Some(SubTrie {
+                trie: self,
+                value: None,
+                child_nodes: &self.raw.top_level_nodes,
+            });
         }
+        let node = self.get_node(prefix)?;
+        Some(SubTrie {
+            trie: self,
+            value: node.value.as_ref(),
+            child_nodes: &node.child_nodes,
+        })
     }
 
-    fn as_ptr(&mut self) -> *mut TrieNode<T> {
-        self as *mut _
+    /// Returns a read-only handle on the node at `key` for walking the trie one char at a time, or
+    /// `None` if no node spells `key`. The empty key gives the root.
+    pub fn node_view(&self, key: &str) -> Option<NodeView<'_, T>> {
+        if key.is_empty() {
+            return Some(NodeView {
+                value: None,
+                child_nodes: &self.raw.top_level_nodes,
+            });
+        }
+        let node = self.get_node(key)?;
+        Some(NodeView {
+            value: node.value.as_ref(),
+            child_nodes: &node.child_nodes,
+        })
     }
 
-    fn insert<I: Iterator<Item = char>>(
-        &mut self,
-        key_iter: I,
-        value: T,
-    ) -> Result<(), &'static str> {
-        // Base case
-        let mut current_trie = self;
+    /// Returns every stored key with the chars that can follow it in a longer key, both in
+    /// ascending order, e.g. to show the branch points of an autocomplete
+    pub fn boundaries(&self) -> Vec<(String, Vec<char>)> {
+        let mut stack = Vec::new();
+        push_sorted_children(&mut stack, "", &self.raw.top_level_nodes);
 
-        for key in key_iter {
-            let node_pointer = current_trie.as_ptr();
-            if let Entry::Vacant(e) = current_trie.child_nodes.entry(key) {
-                let new_trie = TrieNode::new(node_pointer);
-                e.insert(new_trie);
+        let mut boundaries = Vec::new();
+        while let Some((key, node)) = stack.pop() {
+            push_sorted_children(&mut stack, &key, &node.child_nodes);
+            if node.is_end() {
+                let mut next_chars: Vec<_> = node
+                    .child_nodes
+                    .iter()
+                    .filter(|(_, child)| child.has_value())
+                    .map(|(char, _)| *char)
+                    .collect();
+                next_chars.sort_unstable();
+                boundaries.push((key, next_chars));
             }
-            let trie = current_trie.child_nodes.get_mut(&key).unwrap();
-            current_trie = trie;
         }
-        if current_trie.is_end() {
-            Err("Duplicate keys are not allowed")
-        } else {
-            current_trie.value = Some(value);
-            Ok(())
+        boundaries
+    }
+
+    /// Returns the length (in chars) and value of the longest stored key that is a prefix of
+    /// `input`, e.g. for maximal munch tokenization.
+    ///
+    /// `input` is normalized like keys are, so with normalization on the length counts the chars
+    /// of the normalized `input`.
+    pub fn match_longest(&self, input: &str) -> Option<(usize, &T)> {
+        longest_match(&self.raw.top_level_nodes, &self.normalize(input))
+    }
+
+    /// Returns how many leading chars `a` and `b` have in common while also following nodes of
+    /// the trie
+    pub fn longest_shared_path(&self, a: &str, b: &str) -> usize {
+        let (a, b) = (self.normalize(a), self.normalize(b));
+        let mut map = &self.raw.top_level_nodes;
+        let mut depth = 0;
+        for (a, b) in a.chars().zip(b.chars()) {
+            match map.get(&a) {
+                Some(node) if a == b => map = &node.child_nodes,
+                _ => break,
+            }
+            depth += 1;
         }
+        depth
     }
 
-    fn set_value(&mut self, value: Option<T>) {
-        self.value = value;
+    /// Returns the value of the longest stored key that is a prefix of `key` (or `key` itself),
+    /// e.g. so that `"a.b.c"` falls back to the setting at `"a.b"` or `"a"`
+    pub fn ancestor_value(&self, key: &str) -> Option<&T> {
+        let key = self.normalize(key);
+        longest_match(&self.raw.top_level_nodes, &key).map(|(_, value)| value)
     }
 
-    fn get_node_mut(&mut self, key: &char) -> Option<&mut TrieNode<T>> {
-        self.child_nodes.get_mut(key)
+    /// Returns every stored key that is a prefix of `key` (or `key` itself) with its value, from
+    /// the shortest to the longest, i.e. all the layers `ancestor_value` picks the last one of
+    pub fn resolve_chain(&self, key: &str) -> Vec<(String, &T)> {
+        let key = self.normalize(key);
+        let mut chain = Vec::new();
+        let mut map = &self.raw.top_level_nodes;
+        for (end, char) in key.char_indices() {
+            let Some(node) = map.get(&char) else {
+                break;
+            };
+            if let Some(value) = &node.value {
+                chain.push((key[..end + char.len_utf8()].to_owned(), value));
+            }
+            map = &node.child_nodes;
+        }
+        chain
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Returns every node from the top level down to the one at `key`, with its key and value,
+    /// e.g. for breadcrumbs in a hierarchy. Empty if there is no node at `key`.
+    pub fn ancestors(&self, key: &str) -> Vec<(String, Option<&T>)> {
+        let key = self.normalize(key);
+        let mut ancestors = Vec::new();
+        let mut map = &self.raw.top_level_nodes;
+        for (end, char) in key.char_indices() {
+            let Some(node) = map.get(&char) else {
+                return Vec::new();
+            };
+            let end = end + char.len_utf8();
+            ancestors.push((key[..end].to_owned(), node.value.as_ref()));
+            map = &node.child_nodes;
+        }
+        ancestors
+    }
 
-    #[test]
-    fn insert_single_value() {
-        // Create new `Trie`
-        let mut trie = Trie::new();
+    /// Returns the smallest stored key starting with `prefix`, e.g. as an example for a hint,
+    /// without collecting the others like `keys_with_prefix`
+    pub fn any_key_with_prefix(&self, prefix: &str) -> Option<String> {
+        let prefix = self.normalize(prefix);
+        if prefix.is_empty() {
+            let (char, node) = sorted_children(&self.raw.top_level_nodes)
+                .into_iter()
+                .find(|(_, node)| node.subtree_value_count > 0)?;
+            return Some(first_key_in(String::from(char), node));
+        }
+        let node = self.raw.get_node(&prefix)?;
+        (node.subtree_value_count > 0).then(|| first_key_in(prefix.into_owned(), node))
+    }
 
-        // Insert value
-        assert!(trie.insert("hello", 99).is_ok());
+    /// Returns the stored key sharing the longest common prefix with `query`, e.g. for "did you
+    /// mean" suggestions.
+    ///
+    /// Among the keys sharing that prefix, the first one ordered after `query` wins, then the
+    /// last one ordered before it.
+    pub fn nearest_key(&self, query: &str) -> Option<String> {
+        let query: Vec<char> = self.normalize(query).chars().collect();
 
-        // Get value
-        assert_eq!(trie.get("hello"), Some(&99));
+        // The children along the way down `query`, with the node owning them
+        let mut path = vec![(&self.raw.top_level_nodes, None)];
+        for key in &query {
+            match path.last().unwrap().0.get(key) {
+                Some(node) => path.push((&node.child_nodes, Some(node))),
+                None => break,
+            }
+        }
 
-        // Delete value
-        assert!(trie.remove("hello").is_ok());
+        // Fall back to shorter prefixes in case a subtree has no values left
+        while let Some((map, node)) = path.pop() {
+            let prefix: String = query[..path.len()].iter().collect();
+            let next_key = query.get(path.len());
+            let is_end = node.is_some_and(TrieNode::is_end);
 
-        // Get value
-        assert_eq!(trie.get("hello"), None);
+            if next_key.is_none() && is_end {
+                return Some(prefix);
+            }
+            let children = sorted_children(map);
+            let mut children = children.iter().filter(|(_, child)| child.has_value());
+            if let Some((key, child)) = children
+                .clone()
+                .find(|(key, _)| next_key.is_none_or(|next_key| key > next_key))
+            {
+                return Some(first_key_in(format!("{}{}", prefix, key), child));
+            }
+            if let Some((key, child)) = children.rfind(|(key, _)| Some(key) < next_key) {
+                return Some(last_key_in(format!("{}{}", prefix, key), child));
+            }
+            if is_end {
+                return Some(prefix);
+            }
+        }
+        None
     }
 
-    #[test]
-    fn insert_multiple_values() {
-        // Create new `Trie`
-        let mut trie = Trie::new();
+    pub fn insert(&mut self, key: &str, value: T) -> Result<(), TrieError> {
+        self.insert_with_policy(key, value, self.policy)
+    }
 
-        // Insert values
-        assert!(trie.insert("hello", 1).is_ok());
-        assert!(trie.insert("hell", 2).is_ok());
-        assert!(trie.insert("hel", 3).is_ok());
-        assert!(trie.insert("hey", 4).is_ok());
-        assert!(trie.insert("back", 4).is_ok());
+    /// Inserts `value` only if `key` has no value, and returns whether it did. Unlike `insert`,
+    /// a stored value is never replaced or reported as an error, whatever the duplicate policy.
+    ///
+    /// Keys that `insert` rejects for other reasons, e.g. for being empty or too long, also
+    /// return `false`.
+    pub fn insert_if_absent(&mut self, key: &str, value: T) -> bool {
+        self.insert_with_policy(key, value, DuplicatePolicy::Reject)
+            .is_ok()
+    }
 
-        println!("{:?}", &trie);
+    fn insert_with_policy(
+        &mut self,
+        key: &str,
+        value: T,
+        policy: DuplicatePolicy,
+    ) -> Result<(), TrieError> {
+        match self.try_entry(key)? {
+            Entry::Occupied(entry) => entry.node.insert(value, policy),
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+                Ok(())
+            }
+        }
+    }
 
-        // Get values
-        assert_eq!(trie.get("hello"), Some(&1));
-        assert_eq!(trie.get("hell"), Some(&2));
-        assert_eq!(trie.get("hel"), Some(&3));
-        assert_eq!(trie.get("hey"), Some(&4));
+    /// Returns a cursor for inserting many keys that share long prefixes with each other
+    pub fn insert_cursor(&mut self) -> InsertCursor<'_, T> {
+        InsertCursor {
+            trie: self,
+            path: Vec::new(),
+        }
+    }
 
-        // Delete value
-        assert!(trie.remove("hello").is_ok());
-        assert!(trie.remove("hello").is_err());
-        assert!(trie.remove("hell").is_ok());
-        assert!(trie.remove("hel").is_ok());
-        println!("{:?}", &trie);
-        assert!(trie.remove("hey").is_ok());
-        println!("{:?}", &trie);
-        assert!(trie.remove("back").is_ok());
-        println!("{:?}", &trie);
+    /// Sorts `pairs` by key, folds the values of equal keys into the first one with `combine`,
+    /// and inserts the results with an `InsertCursor`, e.g. to count words from a token stream.
+    ///
+    /// Keys already stored are handled like in `Trie::insert`. Stops at the first insert that
+    /// fails and returns its key with the error; the pairs after it are left in `pairs`.
+    pub fn insert_sorted_dedup(
+        &mut self,
+        pairs: &mut Vec<(String, T)>,
+        combine: impl Fn(&mut T, T),
+    ) -> Result<(), (String, TrieError)> {
+        pairs.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut folded: Vec<(String, T)> = Vec::with_capacity(pairs.len());
+        for (key, value) in pairs.drain(..) {
+            match folded.last_mut() {
+                Some((last_key, last_value)) if *last_key == key => combine(last_value, value),
+                _ => folded.push((key, value)),
+            }
+        }
 
-        // Get value
-        assert_eq!(trie.get("hello"), None);
+        let mut cursor = self.insert_cursor();
+        let mut rest = folded.into_iter();
+        while let Some((key, value)) = rest.next() {
+            if let Err(error) = cursor.insert(&key, value) {
+                pairs.extend(rest);
+                return Err((key, error));
+            }
+        }
+        Ok(())
     }
 
-    #[test]
-    fn insert_multiple_values_with_same_key() {
-        // Create new `Trie`
-        let mut trie = Trie::new();
+    // Returns `key` normalized, if the trie's configuration allows storing a value at it
+    fn check_new_key<'a>(&self, key: &'a str) -> Result<Cow<'a, str>, TrieError> {
+        let key = self.check_key(key)?;
+        self.check_new_path(&[], &self.raw.top_level_nodes, &key)?;
+        Ok(key)
+    }
 
-        // Insert multiple values with same key
-        assert!(trie.insert("hello", 1).is_ok());
-        assert!(trie.insert("hello", 5).is_err());
+    // Fails if the configuration rules out a value at the key spelled by `path` and then `rest`.
+    // `path` holds the nodes an `InsertCursor` took out of the trie, and `map` the children of
+    // the last one (or the top level nodes).
+    fn check_new_path(
+        &self,
+        path: &[(char, TrieNode<T>)],
+        map: &ChildMap<T>,
+        rest: &str,
+    ) -> Result<(), TrieError> {
+        if self.terminal_only {
+            check_prefix_conflict(path, map, rest)?;
+        }
+        if let Some(max) = self.max_fanout {
+            check_fanout(map, rest, max)?;
+        }
+        Ok(())
+    }
+
+    // Returns `key` normalized, if it's allowed to get a value
+    fn check_key<'a>(&self, key: &'a str) -> Result<Cow<'a, str>, TrieError> {
+        if key.is_empty() {
+            return Err(TrieError::EmptyKey);
+        }
+        let key = self.normalize(key);
+        if let Some(max) = self.max_key_len {
+            if key.chars().nth(max).is_some() {
+                let len = key.chars().count();
+                return Err(TrieError::KeyTooLong { len, max });
+            }
+        }
+        Ok(key)
+    }
+
+    /// Returns the value at `key`, first inserting the one returned by `f` if there is none.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is empty or the trie's configuration rejects it, e.g. for being
+    /// too long; `try_get_or_insert_with` returns the error instead.
+    pub fn get_or_insert_with(&mut self, key: &str, f: impl FnOnce() -> T) -> &mut T {
+        self.try_get_or_insert_with(key, f)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like `get_or_insert_with`, but returns the error instead of panicking
+    pub fn try_get_or_insert_with(
+        &mut self,
+        key: &str,
+        f: impl FnOnce() -> T,
+    ) -> Result<&mut T, TrieError> {
+        // `f` runs before anything is counted, so a panic in it leaves the counts right
+        Ok(self.try_entry(key)?.or_insert_with(f))
+    }
+
+    /// Stores `value` at `key` and returns the value it replaced, like `Cell::replace`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is empty or the trie's configuration rejects it, e.g. for being
+    /// too long; `try_swap_value` returns the error instead.
+    pub fn swap_value(&mut self, key: &str, value: T) -> Option<T> {
+        self.try_swap_value(key, value)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like `swap_value`, but returns the error instead of panicking
+    pub fn try_swap_value(&mut self, key: &str, value: T) -> Result<Option<T>, TrieError> {
+        Ok(match self.try_entry(key)? {
+            Entry::Occupied(mut entry) => Some(entry.insert(value)),
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+                None
+            }
+        })
+    }
+
+    /// Replaces the value at `key` and returns the old one, or returns `KeyNotFound` if there is
+    /// none; unlike `swap_value` this never creates nodes
+    pub fn set_existing(&mut self, key: &str, value: T) -> Result<T, TrieError> {
+        self.get_mut(key)
+            .map(|old_value| std::mem::replace(old_value, value))
+            .ok_or(TrieError::KeyNotFound)
+    }
+
+    /// Returns the entry for `key`, to inspect and then update or fill it in with one walk.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is empty or the trie's configuration rejects it, e.g. for being
+    /// too long; `try_entry` returns the error instead.
+    pub fn entry(&mut self, key: &str) -> Entry<'_, T> {
+        self.try_entry(key)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like `entry`, but returns the error instead of panicking
+    pub fn try_entry(&mut self, key: &str) -> Result<Entry<'_, T>, TrieError> {
+        let key = self.check_new_key(key)?;
+        let mut key_iter = key.chars();
+        let mut current_key = key_iter.next().unwrap();
+        let len = &mut self.len;
+        #[cfg(feature = "insertion-order")]
+        let next_sequence = &mut self.next_sequence;
+
+        // Each char is hashed once: by the lookup that finds its node, or that misses and keeps
+        // the vacant slot
+        let child_capacity = self.raw.child_capacity;
+        let mut map = &mut self.raw.top_level_nodes;
+        let mut ancestor_counts = Vec::new();
+        let position = loop {
+            match map.entry(current_key) {
+                hash_map::Entry::Occupied(entry) => {
+                    let node = entry.into_mut();
+                    match key_iter.next() {
+                        Some(key) => {
+                            let TrieNode {
+                                child_nodes,
+                                subtree_value_count,
+                                ..
+                            } = node;
+                            ancestor_counts.push(subtree_value_count);
+                            current_key = key;
+                            map = child_nodes;
+                        }
+                        None if node.is_end() => {
+                            return Ok(Entry::Occupied(OccupiedEntry { node }))
+                        }
+                        None => break VacantPosition::Node(node),
+                    }
+                }
+                hash_map::Entry::Vacant(entry) => {
+                    break VacantPosition::Missing {
+                        entry,
+                        suffix: key_iter.collect(),
+                    }
+                }
+            }
+        };
+
+        Ok(Entry::Vacant(VacantEntry {
+            position,
+            ancestor_counts,
+            child_capacity,
+            len,
+            #[cfg(feature = "insertion-order")]
+            next_sequence,
+        }))
+    }
+
+    /// Returns the value at `key`, first inserting `T::default()` if there is none.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is empty or the trie's configuration rejects it, e.g. for being
+    /// too long; `try_entry_or_default` returns the error instead.
+    pub fn entry_or_default(&mut self, key: &str) -> &mut T
+    where
+        T: Default,
+    {
+        self.get_or_insert_with(key, T::default)
+    }
+
+    /// Like `entry_or_default`, but returns the error instead of panicking
+    pub fn try_entry_or_default(&mut self, key: &str) -> Result<&mut T, TrieError>
+    where
+        T: Default,
+    {
+        self.try_get_or_insert_with(key, T::default)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Result<(), TrieError> {
+        if key.is_empty() {
+            return Err(TrieError::EmptyKey);
+        }
+
+        self.remove_value_if(key, |_| true)
+            .map(|_| ())
+            .ok_or(TrieError::KeyNotFound)
+    }
+
+    /// Removes the value at `key` like `remove`, also reporting how many nodes that freed, e.g.
+    /// to keep external size accounting in sync with `node_count`
+    pub fn remove_report(&mut self, key: &str) -> Result<RemoveReport<T>, TrieError> {
+        if key.is_empty() {
+            return Err(TrieError::EmptyKey);
+        }
+        let key = self.normalize(key);
+        let (value, pruned_nodes) = self
+            .raw
+            .remove_value_and_prune_if(&key, |_| true)
+            .ok_or(TrieError::KeyNotFound)?;
+        self.len -= 1;
+        Ok(RemoveReport {
+            value,
+            pruned_nodes,
+        })
+    }
+
+    /// Removes the value at `key` and, if the keys below it form a single unbranched chain,
+    /// returns the nearest of them.
+    ///
+    /// That key takes the place of the removed one: the nodes spelling it are kept unchanged, so
+    /// it stays reachable. Returns `Ok(None)` if `key` has no descendants or they branch.
+    pub fn remove_and_promote(&mut self, key: &str) -> Result<Option<String>, TrieError> {
+        if key.is_empty() {
+            return Err(TrieError::EmptyKey);
+        }
+        let key = self.normalize(key).into_owned();
+        let node = self
+            .get_node(&key)
+            .filter(|node| node.is_end())
+            .ok_or(TrieError::KeyNotFound)?;
+
+        let mut promoted = None;
+        let mut chain_key = key.clone();
+        let mut current_node = node;
+        while current_node.child_nodes.len() == 1 {
+            let (child_key, child) = current_node.child_nodes.iter().next().unwrap();
+            chain_key.push(*child_key);
+            if child.is_end() {
+                promoted = Some(chain_key);
+                break;
+            }
+            current_node = child;
+        }
+
+        self.remove_value_if(&key, |_| true);
+        Ok(promoted)
+    }
+
+    /// Removes the value at `key` only if `pred` returns `true` for it; returns whether it removed
+    pub fn remove_if(&mut self, key: &str, pred: impl FnOnce(&T) -> bool) -> bool {
+        self.remove_value_if(key, pred).is_some()
+    }
+
+    // Returns the node at the normalized `key`, if any
+    fn get_node(&self, key: &str) -> Option<&TrieNode<T>> {
+        self.raw.get_node(&self.normalize(key))
+    }
+
+    fn get_node_mut(&mut self, key: &str) -> Option<&mut TrieNode<T>> {
+        let key = self.normalize(key);
+        self.raw.get_node_mut(&key)
+    }
+
+    // Returns `key` in the configured normalization form, borrowing it when already normalized
+    fn normalize<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        #[cfg(feature = "unicode-normalization")]
+        {
+            use unicode_normalization::{is_nfc, is_nfd, UnicodeNormalization};
+
+            match self.normalization {
+                Normalization::Nfc if !is_nfc(key) => return Cow::Owned(key.nfc().collect()),
+                Normalization::Nfd if !is_nfd(key) => return Cow::Owned(key.nfd().collect()),
+                _ => {}
+            }
+        }
+        Cow::Borrowed(key)
+    }
+
+    fn remove_value_if(&mut self, key: &str, pred: impl FnOnce(&T) -> bool) -> Option<T> {
+        let key = self.normalize(key);
+        let value = self.raw.remove_value_if(&key, pred);
+        self.len -= usize::from(value.is_some());
+        value
+    }
+
+    /// Removes every key starting with `prefix` and returns them with their values, in
+    /// lexicographic order
+    pub fn drain_prefix(&mut self, prefix: &str) -> Vec<(String, T)> {
+        let prefix = self.normalize(prefix);
+        let mut stack = Vec::new();
+        if prefix.is_empty() {
+            let top_level_nodes = std::mem::take(&mut self.raw.top_level_nodes);
+            push_sorted_owned_children(&mut stack, "", top_level_nodes);
+        } else if let Some(node) = self.raw.take_subtree(&prefix) {
+            stack.push((prefix.into_owned(), node));
+        }
+        let drained: Vec<_> = IntoIter { stack }.collect();
+        self.len -= drained.len();
+        drained
+    }
+
+    /// Removes every key starting with `prefix` and returns how many were removed
+    pub fn remove_prefix(&mut self, prefix: &str) -> usize {
+        let prefix = self.normalize(prefix);
+        let mut stack: Vec<_> = if prefix.is_empty() {
+            std::mem::take(&mut self.raw.top_level_nodes)
+                .into_values()
+                .collect()
+        } else {
+            self.raw.take_subtree(&prefix).into_iter().collect()
+        };
+
+        let mut removed = 0;
+        while let Some(mut node) = stack.pop() {
+            removed += usize::from(node.is_end());
+            stack.extend(node.child_nodes.drain().map(|(_, node)| node));
+        }
+        self.len -= removed;
+        removed
+    }
+
+    /// Drops the value of every key starting with `prefix` but keeps the nodes, so that the same
+    /// keys can be inserted again without allocating. Returns how many values were dropped.
+    ///
+    /// Unlike `remove_prefix`, `node_count` is unchanged. `starts_with` still only looks at
+    /// stored keys, so it is false for `prefix` until one is inserted again.
+    pub fn clear_values_prefix(&mut self, prefix: &str) -> usize {
+        let prefix = self.normalize(prefix);
+        let mut stack: Vec<_> = if prefix.is_empty() {
+            self.raw.top_level_nodes.values_mut().collect()
+        } else {
+            let Some(mut path) = self.raw.path_mut(&prefix) else {
+                return 0;
+            };
+            let count = path.node.subtree_value_count;
+            path.add_to_counts(-(count as isize));
+            vec![path.into_node()]
+        };
+
+        let mut cleared = 0;
+        while let Some(node) = stack.pop() {
+            cleared += usize::from(node.value.take().is_some());
+            node.subtree_value_count = 0;
+            stack.extend(node.child_nodes.values_mut());
+        }
+        self.len -= cleared;
+        cleared
+    }
+
+    /// Moves every key starting with `from` to start with `to` instead, e.g. to move the
+    /// `"tmp/"` namespace to `"archive/"`. The nodes are moved, not copied.
+    ///
+    /// Keys that are already stored under the new name follow the duplicate policy; with
+    /// `DuplicatePolicy::Reject`, nothing is moved and `TrieError::DuplicateKey` is returned.
+    pub fn rename_prefix(&mut self, from: &str, to: &str) -> Result<(), TrieError> {
+        if from.is_empty() || to.is_empty() {
+            return Err(TrieError::EmptyKey);
+        }
+        let (from, to) = (self.normalize(from), self.normalize(to));
+        if from == to {
+            return Ok(());
+        }
+        let Some(node) = self.raw.take_subtree(&from) else {
+            return Ok(());
+        };
+
+        if self.policy == DuplicatePolicy::Reject && self.raw.collides_with(&to, &node) {
+            // Put the subtree back where it was
+            self.raw.merge_subtree(&from, node, DuplicatePolicy::Reject);
+            return Err(TrieError::DuplicateKey);
+        }
+        self.len -= self.raw.merge_subtree(&to, node, self.policy);
+        Ok(())
+    }
+
+    /// Applies the operations of `patch` in order.
+    ///
+    /// Stops at the first operation that fails, e.g. an update or removal of a key that isn't
+    /// stored, and returns its index in `patch` with the error; the operations before it stay
+    /// applied.
+    pub fn apply(&mut self, patch: &TriePatch<T>) -> Result<(), (usize, TrieError)>
+    where
+        T: Clone,
+    {
+        for (index, op) in patch.iter().enumerate() {
+            let result = match op {
+                PatchOp::Insert(key, value) => self.insert(key, value.clone()),
+                PatchOp::Remove(key) => self.remove(key),
+                PatchOp::Update(key, value) => self.set_existing(key, value.clone()).map(|_| ()),
+            };
+            result.map_err(|error| (index, error))?;
+        }
+        Ok(())
+    }
+
+    /// Removes the values at `keys` and returns how many were present.
+    ///
+    /// Nodes are pruned once after every value is taken, instead of once per key.
+    pub fn remove_many(&mut self, keys: &[&str]) -> usize {
+        let keys: Vec<_> = keys.iter().map(|key| self.normalize(key)).collect();
+        let removed = self.raw.remove_many(keys, self.auto_shrink);
+        self.len -= removed;
+        removed
+    }
+
+    /// Shortens every key longer than `max_len` chars to its first `max_len` chars, moving the
+    /// values up and removing the deeper nodes. Returns how many keys were shortened.
+    ///
+    /// Values landing on the same key are merged in lexicographic key order, starting with the
+    /// one already there, as if inserted in that order: with `DuplicatePolicy::Replace` the last
+    /// one is kept, with the other policies the first one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_len` is 0; `try_truncate_keys` returns an error instead.
+    pub fn truncate_keys(&mut self, max_len: usize) -> usize {
+        assert!(max_len > 0, "Keys can not be truncated to the empty key");
+        let policy = self.policy;
+        let mut truncated = 0;
+        let mut dropped = 0;
+        retain_nodes(
+            &mut self.raw.top_level_nodes,
+            self.auto_shrink,
+            |key, node| {
+                if key.chars().nth(max_len - 1).is_none() {
+                    return;
+                }
+                let child_nodes = std::mem::take(&mut node.child_nodes);
+                for (_, value) in IntoIter::new(child_nodes) {
+                    truncated += 1;
+                    if !node.is_end() {
+                        node.value = Some(value);
+                        #[cfg(feature = "insertion-order")]
+                        {
+                            node.sequence = self.next_sequence;
+                            self.next_sequence += 1;
+                        }
+                    } else {
+                        dropped += 1;
+                        if policy == DuplicatePolicy::Replace {
+                            node.value = Some(value);
+                        }
+                    }
+                }
+            },
+        );
+        self.len -= dropped;
+        truncated
+    }
+
+    /// Like `truncate_keys`, but returns `TrieError::EmptyKey` instead of panicking if
+    /// `max_len` is 0
+    pub fn try_truncate_keys(&mut self, max_len: usize) -> Result<usize, TrieError> {
+        if max_len == 0 {
+            return Err(TrieError::EmptyKey);
+        }
+        Ok(self.truncate_keys(max_len))
+    }
+
+    /// Keeps only the values for which `f` returns `true`, pruning the nodes left without values
+    pub fn retain(&mut self, mut f: impl FnMut(&str, &mut T) -> bool) {
+        let mut removed = 0;
+        retain_nodes(
+            &mut self.raw.top_level_nodes,
+            self.auto_shrink,
+            |key, node| {
+                if let Some(value) = &mut node.value {
+                    if !f(key, value) {
+                        node.value = None;
+                        removed += 1;
+                    }
+                }
+            },
+        );
+        self.len -= removed;
+    }
+
+    /// Sets every value to `None` but keeps all nodes, so the same keys can be filled in again
+    /// without allocating.
+    ///
+    /// The kept nodes are pruned by the next `remove_many` or `retain`.
+    pub fn clear_values(&mut self) {
+        let mut stack: Vec<_> = self.raw.top_level_nodes.values_mut().collect();
+        while let Some(node) = stack.pop() {
+            node.value = None;
+            node.subtree_value_count = 0;
+            stack.extend(node.child_nodes.values_mut());
+        }
+        self.len = 0;
+    }
+
+    /// Returns the number of stored values
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of nodes, including the ones without a value
+    pub fn node_count(&self) -> usize {
+        self.count_nodes().0
+    }
+
+    /// Consumes the trie and builds a `RadixTrie` with the same keys and values, collapsing
+    /// every chain of valueless single-child nodes into one edge.
+    ///
+    /// The radix trie does not normalize keys, so query it with keys in the form they were
+    /// stored in.
+    pub fn to_radix(self) -> RadixTrie<T> {
+        RadixTrie::from_nodes(self.raw.top_level_nodes, self.len)
+    }
+
+    /// Counts the nodes with a value by walking the trie.
+    ///
+    /// This always equals `len`, which is maintained instead of computed; compare it with
+    /// `node_count` to see how many nodes each key needs.
+    pub fn value_count(&self) -> usize {
+        self.count_nodes().1
+    }
+
+    /// Returns the number of nodes with more than one child, i.e. where keys branch apart
+    pub fn distinct_prefix_count(&self) -> usize {
+        let mut count = 0;
+        let mut stack: Vec<_> = self.raw.top_level_nodes.values().collect();
+        while let Some(node) = stack.pop() {
+            if node.child_nodes.len() > 1 {
+                count += 1;
+            }
+            stack.extend(node.child_nodes.values());
+        }
+        count
+    }
+
+    /// Counts how many edges (nodes) of the whole trie are labeled with each `char`
+    pub fn edge_histogram(&self) -> HashMap<char, usize> {
+        let mut histogram = HashMap::new();
+        let mut stack = vec![&self.raw.top_level_nodes];
+        while let Some(map) = stack.pop() {
+            for (key, node) in map {
+                *histogram.entry(*key).or_insert(0) += 1;
+                stack.push(&node.child_nodes);
+            }
+        }
+        histogram
+    }
+
+    // Returns the number of nodes and of nodes with a value
+    fn count_nodes(&self) -> (usize, usize) {
+        let mut counts = (0, 0);
+        let mut stack: Vec<_> = self.raw.top_level_nodes.values().collect();
+        while let Some(node) = stack.pop() {
+            counts.0 += 1;
+            if node.is_end() {
+                counts.1 += 1;
+            }
+            stack.extend(node.child_nodes.values());
+        }
+        counts
+    }
+
+    /// Walks every node depth first, visiting children in ascending `char` order.
+    ///
+    /// For each node `visitor.enter` is called before its children are walked, and
+    /// `visitor.leave` after.
+    pub fn walk(&self, visitor: &mut impl TrieVisitor<T>) {
+        enum Step<'a, T> {
+            Enter(char, &'a TrieNode<T>),
+            Leave,
+        }
+
+        let mut partial_key = String::new();
+        let mut stack: Vec<_> = sorted_children(&self.raw.top_level_nodes)
+            .into_iter()
+            .rev()
+            .map(|(char, node)| Step::Enter(char, node))
+            .collect();
+
+        while let Some(step) = stack.pop() {
+            match step {
+                Step::Enter(char, node) => {
+                    partial_key.push(char);
+                    visitor.enter(&partial_key, node.value.as_ref());
+                    stack.push(Step::Leave);
+                    stack.extend(
+                        sorted_children(&node.child_nodes)
+                            .into_iter()
+                            .rev()
+                            .map(|(char, node)| Step::Enter(char, node)),
+                    );
+                }
+                Step::Leave => {
+                    visitor.leave(&partial_key);
+                    partial_key.pop();
+                }
+            }
+        }
+    }
+
+    /// Returns the keys and values in lexicographic key order
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.iter_prefix("")
+    }
+
+    /// Returns the keys and values breadth first: shorter keys before longer ones, and keys of the
+    /// same length in lexicographic order, e.g. to list the most general matches first
+    pub fn iter_bfs(&self) -> Vec<(String, &T)> {
+        let mut pairs = Vec::new();
+        let mut queue: VecDeque<_> = sorted_children(&self.raw.top_level_nodes)
+            .into_iter()
+            .map(|(char, node)| (String::from(char), node))
+            .collect();
+        while let Some((key, node)) = queue.pop_front() {
+            for (char, child) in sorted_children(&node.child_nodes) {
+                queue.push_back((format!("{}{}", key, char), child));
+            }
+            if let Some(value) = &node.value {
+                pairs.push((key, value));
+            }
+        }
+        pairs
+    }
+
+    /// Returns the keys and mutable references to their values in lexicographic key order
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        let mut stack = Vec::new();
+        push_sorted_children_mut(&mut stack, "", &mut self.raw.top_level_nodes);
+        IterMut { stack }
+    }
+
+    /// Consumes the trie, returning its keys in lexicographic order
+    pub fn into_keys(self) -> impl Iterator<Item = String> {
+        self.into_iter().map(|(key, _)| key)
+    }
+
+    /// Consumes the trie, returning its values in lexicographic order of their keys
+    pub fn into_values(self) -> impl Iterator<Item = T> {
+        self.into_iter().map(|(_, value)| value)
+    }
+
+    /// Returns the stored keys no longer key starts with, in lexicographic order, e.g. the files
+    /// of a path trie whose other keys are directories
+    pub fn leaf_keys(&self) -> Vec<String> {
+        let mut stack = Vec::new();
+        push_sorted_children(&mut stack, "", &self.raw.top_level_nodes);
+
+        let mut keys = Vec::new();
+        while let Some((key, node)) = stack.pop() {
+            if node.is_end() && !node.child_nodes.values().any(TrieNode::has_value) {
+                keys.push(key);
+            } else {
+                push_sorted_children(&mut stack, &key, &node.child_nodes);
+            }
+        }
+        keys
+    }
+
+    /// Maps every stored key to its shortest prefix that no other stored key starts with, like
+    /// git's abbreviated commands. A key that is a prefix of another key maps to itself.
+    pub fn unique_prefixes(&self) -> HashMap<String, String> {
+        let mut prefixes = HashMap::new();
+        let mut stack: Vec<_> = self
+            .raw
+            .top_level_nodes
+            .iter()
+            .map(|(char, node)| (String::from(*char), node))
+            .collect();
+        while let Some((prefix, node)) = stack.pop() {
+            if node.subtree_value_count == 1 {
+                // Follow the only key below down to its end
+                let mut key = prefix.clone();
+                let mut node = node;
+                while !node.is_end() {
+                    let (char, child) = node
+                        .child_nodes
+                        .iter()
+                        .find(|(_, child)| child.subtree_value_count > 0)
+                        .unwrap();
+                    key.push(*char);
+                    node = child;
+                }
+                prefixes.insert(key, prefix);
+                continue;
+            }
+            if node.is_end() {
+                prefixes.insert(prefix.clone(), prefix.clone());
+            }
+            for (char, child) in &node.child_nodes {
+                stack.push((format!("{}{}", prefix, char), child));
+            }
+        }
+        prefixes
+    }
+
+    /// Returns every key and its value, largest value first, e.g. to rank words by frequency.
+    /// Keys with equal values stay in lexicographic order.
+    pub fn keys_by_value(&self) -> Vec<(String, &T)>
+    where
+        T: Ord,
+    {
+        let mut entries: Vec<_> = self.iter().collect();
+        // Stable, so ties keep the key order of `iter`
+        entries.sort_by(|(_, a), (_, b)| b.cmp(a));
+        entries
+    }
+
+    /// Returns the sum of all values, e.g. the total of per-key counters
+    pub fn sum_values(&self) -> T
+    where
+        T: Sum + Clone,
+    {
+        self.values().cloned().sum()
+    }
+
+    /// Returns the largest value, or `None` if the trie is empty
+    pub fn max_value(&self) -> Option<&T>
+    where
+        T: Ord,
+    {
+        self.values().max()
+    }
+
+    // Visits the values in no particular order, without building their keys
+    fn values(&self) -> impl Iterator<Item = &T> {
+        let mut stack: Vec<_> = self.raw.top_level_nodes.values().collect();
+        std::iter::from_fn(move || {
+            while let Some(node) = stack.pop() {
+                stack.extend(node.child_nodes.values());
+                if let Some(value) = &node.value {
+                    return Some(value);
+                }
+            }
+            None
+        })
+    }
+
+    /// Returns every stored key in the order it was first given a value.
+    ///
+    /// Replacing a value keeps the key's position; removing and inserting it again moves it to
+    /// the end.
+    #[cfg(feature = "insertion-order")]
+    pub fn keys_by_insertion(&self) -> Vec<String> {
+        let mut stack = Vec::new();
+        push_sorted_children(&mut stack, "", &self.raw.top_level_nodes);
+
+        let mut keys = Vec::new();
+        while let Some((key, node)) = stack.pop() {
+            push_sorted_children(&mut stack, &key, &node.child_nodes);
+            if node.is_end() {
+                keys.push((node.sequence, key));
+            }
+        }
+        keys.sort_unstable_by_key(|(sequence, _)| *sequence);
+        keys.into_iter().map(|(_, key)| key).collect()
+    }
+
+    /// Returns the keys on one line, with shared prefixes written once and the keys continuing
+    /// them as alternatives, e.g. `"back,he(llo|y)"`, for logging what a small trie holds.
+    ///
+    /// Keys are in lexicographic order, and an empty alternative marks a key that is a prefix
+    /// of others, as in `"he(|llo)"`.
+    pub fn compact_summary(&self) -> String {
+        enum Step<'a, T> {
+            Text(&'static str),
+            // Emit the node's char and everything below it
+            Node(char, &'a TrieNode<T>),
+        }
+
+        // The children leading to values, in ascending order
+        fn valued_children<T>(node: &TrieNode<T>) -> Vec<(char, &TrieNode<T>)> {
+            sorted_children(&node.child_nodes)
+                .into_iter()
+                .filter(|(_, child)| child.subtree_value_count > 0)
+                .collect()
+        }
+
+        // Pushed last step first, so they are popped in writing order
+        let mut stack = Vec::new();
+        let top_level: Vec<_> = sorted_children(&self.raw.top_level_nodes)
+            .into_iter()
+            .filter(|(_, node)| node.subtree_value_count > 0)
+            .collect();
+        for (i, (char, node)) in top_level.into_iter().enumerate().rev() {
+            stack.push(Step::Node(char, node));
+            if i > 0 {
+                stack.push(Step::Text(","));
+            }
+        }
+
+        let mut summary = String::new();
+        while let Some(step) = stack.pop() {
+            let (char, mut node) = match step {
+                Step::Text(text) => {
+                    summary.push_str(text);
+                    continue;
+                }
+                Step::Node(char, node) => (char, node),
+            };
+            summary.push(char);
+            let mut children = valued_children(node);
+            while !node.is_end() && children.len() == 1 {
+                let (char, child) = children[0];
+                summary.push(char);
+                node = child;
+                children = valued_children(node);
+            }
+            if children.is_empty() {
+                continue;
+            }
+
+            summary.push('(');
+            stack.push(Step::Text(")"));
+            for (i, (char, child)) in children.into_iter().enumerate().rev() {
+                stack.push(Step::Node(char, child));
+                if i > 0 || node.is_end() {
+                    stack.push(Step::Text("|"));
+                }
+            }
+        }
+        summary
+    }
+
+    /// Returns per depth statistics, starting with the top level nodes at depth 1
+    pub fn level_stats(&self) -> Vec<LevelStat> {
+        let mut stats = Vec::new();
+        let mut level: Vec<&TrieNode<T>> = self.raw.top_level_nodes.values().collect();
+
+        while !level.is_empty() {
+            let mut next_level = Vec::new();
+            let mut value_count = 0;
+            for node in &level {
+                if node.is_end() {
+                    value_count += 1;
+                }
+                next_level.extend(node.child_nodes.values());
+            }
+
+            stats.push(LevelStat {
+                depth: stats.len() + 1,
+                node_count: level.len(),
+                value_count,
+                average_fanout: next_level.len() as f64 / level.len() as f64,
+            });
+            level = next_level;
+        }
+
+        stats
+    }
+}
+
+impl<T: Encode> Trie<T> {
+    /// Streams every key and value to `w` in lexicographic key order.
+    ///
+    /// Each entry is the key's length as a varint, its UTF-8 bytes and then the encoded value;
+    /// a zero length marks the end.
+    pub fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        let mut stack = Vec::new();
+        push_sorted_children(&mut stack, "", &self.raw.top_level_nodes);
+        while let Some((key, node)) = stack.pop() {
+            push_sorted_children(&mut stack, &key, &node.child_nodes);
+            if let Some(value) = &node.value {
+                codec::write_varint(w, key.len() as u64)?;
+                w.write_all(key.as_bytes())?;
+                value.encode(w)?;
+            }
+        }
+        codec::write_varint(w, 0)
+    }
+}
+
+impl<T: Decode> Trie<T> {
+    /// Reads a trie written by `write_to`
+    pub fn read_from(r: &mut impl Read) -> io::Result<Trie<T>> {
+        let mut trie = Trie::new();
+        loop {
+            let len = codec::read_varint(r)?;
+            if len == 0 {
+                return Ok(trie);
+            }
+            let key = String::from_utf8(codec::read_bytes(r, len)?)
+                .map_err(|_| codec::invalid_data("key is not UTF-8"))?;
+            let value = T::decode(r)?;
+            trie.insert(&key, value)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        }
+    }
+}
+
+impl<T: Eq + Hash> Trie<Rc<T>> {
+    /// Makes all equal values share one `Rc`, so the duplicates can be freed, e.g. when many
+    /// words carry the same few tags
+    pub fn intern_values(&mut self) {
+        let mut pool: HashSet<Rc<T>> = HashSet::new();
+        for (_, value) in self.iter_mut() {
+            match pool.get(&**value) {
+                Some(shared) => *value = shared.clone(),
+                None => {
+                    pool.insert(value.clone());
+                }
+            }
+        }
+    }
+}
+
+impl Trie<()> {
+    /// Inserts every line of `reader` as a key, e.g. to load a word list, and returns how many
+    /// keys were new.
+    ///
+    /// Trailing whitespace is trimmed and blank lines are skipped. Keys that are already stored
+    /// are skipped too; other insert errors stop the load with `io::ErrorKind::InvalidData`.
+    pub fn load_lines(&mut self, reader: impl BufRead) -> io::Result<usize> {
+        let len = self.len;
+        for line in reader.lines() {
+            let line = line?;
+            let key = line.trim_end();
+            if key.is_empty() {
+                continue;
+            }
+            match self.insert(key, ()) {
+                Ok(()) | Err(TrieError::DuplicateKey) => {}
+                Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+            }
+        }
+        Ok(self.len - len)
+    }
+}
+
+/// Iterator over the keys and values of a `Trie`, in lexicographic key order
+pub struct Iter<'a, T> {
+    // Nodes still to visit, with their keys, so that the next one to visit is on top
+    stack: Vec<(String, &'a TrieNode<T>)>,
+}
+
+/// A read-only view of the keys of a `Trie` below a prefix, returned by `Trie::subtrie`.
+///
+/// Keys are relative to the prefix, so the view of `"he"` reads `"hello"` as `"llo"` and the
+/// prefix itself as `""`.
+pub struct SubTrie<'a, T> {
+    trie: &'a Trie<T>,
+    value: Option<&'a T>,
+    child_nodes: &'a ChildMap<T>,
+}
+
+/// A read-only node of a `Trie`, returned by `Trie::node_view`
+pub struct NodeView<'a, T> {
+    value: Option<&'a T>,
+    child_nodes: &'a ChildMap<T>,
+}
+
+impl<'a, T> NodeView<'a, T> {
+    pub fn value(&self) -> Option<&'a T> {
+        self.value
+    }
+
+    /// Returns whether a key ends at this node
+    pub fn is_endpoint(&self) -> bool {
+        self.value.is_some()
+    }
+
+    /// Returns the chars of the children in ascending order
+    pub fn child_chars(&self) -> Vec<char> {
+        let mut chars: Vec<_> = self.child_nodes.keys().copied().collect();
+        chars.sort_unstable();
+        chars
+    }
+
+    pub fn child(&self, char: char) -> Option<NodeView<'a, T>> {
+        let node = self.child_nodes.get(&char)?;
+        Some(NodeView {
+            value: node.value.as_ref(),
+            child_nodes: &node.child_nodes,
+        })
+    }
+}
+
+impl<'a, T> SubTrie<'a, T> {
+    pub fn get(&self, key: &str) -> Option<&'a T> {
+        let key = self.trie.normalize(key);
+        let mut key_iter = key.chars();
+        let Some(first_key) = key_iter.next() else {
+            return self.value;
+        };
+        let mut node = self.child_nodes.get(&first_key)?;
+        for key in key_iter {
+            node = node.child_nodes.get(&key)?;
+        }
+        node.value.as_ref()
+    }
+
+    /// Returns the relative keys in lexicographic order
+    pub fn keys(&self) -> Vec<String> {
+        self.iter().map(|(key, _)| key).collect()
+    }
+
+    /// Returns the relative keys and their values in lexicographic key order
+    pub fn iter(&self) -> impl Iterator<Item = (String, &'a T)> {
+        let mut stack = Vec::new();
+        push_sorted_children(&mut stack, "", self.child_nodes);
+        self.value
+            .map(|value| (String::new(), value))
+            .into_iter()
+            .chain(Iter { stack })
+    }
+}
+
+/// Iterator over the keys starting with a prefix, returned by `Trie::iter_prefix`
+pub type PrefixIter<'a, T> = Iter<'a, T>;
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (String, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((key, node)) = self.stack.pop() {
+            push_sorted_children(&mut self.stack, &key, &node.child_nodes);
+            if let Some(value) = &node.value {
+                return Some((key, value));
+            }
+        }
+        None
+    }
+}
+
+/// An iterator over the keys and mutable values of a `Trie` in lexicographic key order, returned
+/// by `Trie::iter_mut`
+pub struct IterMut<'a, T> {
+    // Nodes still to visit, with their keys, so that the next one to visit is on top
+    stack: Vec<(String, &'a mut TrieNode<T>)>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = (String, &'a mut T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((key, node)) = self.stack.pop() {
+            let TrieNode {
+                value, child_nodes, ..
+            } = node;
+            push_sorted_children_mut(&mut self.stack, &key, child_nodes);
+            if let Some(value) = value {
+                return Some((key, value));
+            }
+        }
+        None
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut Trie<T> {
+    type Item = (String, &'a mut T);
+    type IntoIter = IterMut<'a, T>;
+
+    fn into_iter(self) -> IterMut<'a, T> {
+        self.iter_mut()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Trie<T> {
+    type Item = (String, &'a T);
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+/// Consuming iterator over the keys and values of a `Trie`, in lexicographic key order
+pub struct IntoIter<T> {
+    stack: Vec<(String, TrieNode<T>)>,
+}
+
+impl<T> IntoIter<T> {
+    pub(crate) fn new(top_level_nodes: ChildMap<T>) -> Self {
+        let mut stack = Vec::new();
+        push_sorted_owned_children(&mut stack, "", top_level_nodes);
+        IntoIter { stack }
+    }
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = (String, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((key, mut node)) = self.stack.pop() {
+            let child_nodes = std::mem::take(&mut node.child_nodes);
+            push_sorted_owned_children(&mut self.stack, &key, child_nodes);
+            if let Some(value) = node.value.take() {
+                return Some((key, value));
+            }
+        }
+        None
+    }
+}
+
+impl<T> IntoIterator for Trie<T> {
+    type Item = (String, T);
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter::new(self.raw.top_level_nodes)
+    }
+}
+
+// Fails if a key on the way to the key spelled by `path` and then `rest`, or below it, has a
+// value; `map` holds the children of the last node of `path`
+fn check_prefix_conflict<T>(
+    path: &[(char, TrieNode<T>)],
+    mut map: &ChildMap<T>,
+    rest: &str,
+) -> Result<(), TrieError> {
+    let ancestors = match rest.is_empty() {
+        true => &path[..path.len() - 1],
+        false => path,
+    };
+    let conflict = ancestors.iter().any(|(_, node)| node.is_end())
+        || (rest.is_empty() && map.values().any(TrieNode::has_value));
+    if conflict {
+        return Err(TrieError::PrefixConflict);
+    }
+
+    let mut key_iter = rest.chars().peekable();
+    while let Some(key) = key_iter.next() {
+        let Some(node) = map.get(&key) else {
+            return Ok(());
+        };
+        let conflict = match key_iter.peek() {
+            Some(_) => node.is_end(),
+            None => node.child_nodes.values().any(TrieNode::has_value),
+        };
+        if conflict {
+            return Err(TrieError::PrefixConflict);
+        }
+        map = &node.child_nodes;
+    }
+    Ok(())
+}
+
+// Fails if a node on the way down `rest` from `map` would need a child past `max`
+fn check_fanout<T>(mut map: &ChildMap<T>, rest: &str, max: usize) -> Result<(), TrieError> {
+    for key in rest.chars() {
+        match map.get(&key) {
+            Some(node) => map = &node.child_nodes,
+            None if map.len() >= max => return Err(TrieError::FanoutExceeded),
+            None => return Ok(()),
+        }
+    }
+    Ok(())
+}
+
+fn sorted_children<T>(map: &ChildMap<T>) -> Vec<(char, &TrieNode<T>)> {
+    let mut children: Vec<_> = map.iter().map(|(char, node)| (*char, node)).collect();
+    children.sort_unstable_by_key(|(char, _)| *char);
+    children
+}
+
+// Returns the smallest key stored under `node`, which is at `key` and must lead to a value
+fn first_key_in<T>(mut key: String, mut node: &TrieNode<T>) -> String {
+    while !node.is_end() {
+        let (child_key, child) = sorted_children(&node.child_nodes)
+            .into_iter()
+            .find(|(_, child)| child.subtree_value_count > 0)
+            .unwrap();
+        key.push(child_key);
+        node = child;
+    }
+    key
+}
+
+// Returns the largest key stored under `node`, which is at `key` and must lead to a value
+fn last_key_in<T>(mut key: String, mut node: &TrieNode<T>) -> String {
+    while let Some((child_key, child)) = sorted_children(&node.child_nodes)
+        .into_iter()
+        .rfind(|(_, child)| child.has_value())
+    {
+        key.push(child_key);
+        node = child;
+    }
+    key
+}
+
+// Returns the length (in chars) and value of the longest key below `map` that is a prefix of
+// `input`
+fn longest_match<'a, T>(mut map: &'a ChildMap<T>, input: &str) -> Option<(usize, &'a T)> {
+    let mut longest_match = None;
+    for (depth, key) in input.chars().enumerate() {
+        match map.get(&key) {
+            Some(node) => {
+                if let Some(value) = &node.value {
+                    longest_match = Some((depth + 1, value));
+                }
+                map = &node.child_nodes;
+            }
+            None => break,
+        }
+    }
+    longest_match
+}
+
+fn get_value_mut<'a, T>(map: &'a mut ChildMap<T>, key: &str) -> Option<&'a mut T> {
+    let mut key_iter = key.chars();
+    map.get_mut(&key_iter.next()?)?
+        .get_descendant_mut(key_iter)?
+        .value
+        .as_mut()
+}
+
+// Like `push_sorted_children`, but moves the children out of `map`
+fn push_sorted_owned_children<T>(
+    stack: &mut Vec<(String, TrieNode<T>)>,
+    key: &str,
+    map: ChildMap<T>,
+) {
+    let mut children: Vec<_> = map.into_iter().collect();
+    children.sort_unstable_by(|(a, _), (b, _)| b.cmp(a));
+    for (char, child) in children {
+        let mut child_key = String::with_capacity(key.len() + char.len_utf8());
+        child_key.push_str(key);
+        child_key.push(char);
+        stack.push((child_key, child));
+    }
+}
+
+// Like `push_sorted_children`, but for mutable access to the children
+fn push_sorted_children_mut<'a, T>(
+    stack: &mut Vec<(String, &'a mut TrieNode<T>)>,
+    key: &str,
+    map: &'a mut ChildMap<T>,
+) {
+    let mut children: Vec<_> = map.iter_mut().collect();
+    children.sort_unstable_by(|(a, _), (b, _)| b.cmp(a));
+    for (char, child) in children {
+        let mut child_key = String::with_capacity(key.len() + char.len_utf8());
+        child_key.push_str(key);
+        child_key.push(*char);
+        stack.push((child_key, child));
+    }
+}
+
+// Pushes the children in `map` (owned by the node at `key`) so they are popped in ascending order
+fn push_sorted_children<'a, T>(
+    stack: &mut Vec<(String, &'a TrieNode<T>)>,
+    key: &str,
+    map: &'a ChildMap<T>,
+) {
+    for (char, child) in sorted_children(map).into_iter().rev() {
+        let mut child_key = String::with_capacity(key.len() + char.len_utf8());
+        child_key.push_str(key);
+        child_key.push(char);
+        stack.push((child_key, child));
+    }
+}
+
+/// A `Trie` holding values of any type, e.g. for a plugin registry
+pub type AnyTrie = Trie<Box<dyn Any + Send + Sync>>;
+
+impl AnyTrie {
+    /// Inserts like `insert`; the value can be read back with `get_downcast`
+    pub fn insert_boxed(
+        &mut self,
+        key: &str,
+        value: Box<dyn Any + Send + Sync>,
+    ) -> Result<(), TrieError> {
+        self.insert(key, value)
+    }
+
+    /// Returns the value at `key` if it is a `V`
+    pub fn get_downcast<V: 'static>(&self, key: &str) -> Option<&V> {
+        self.get(key)?.downcast_ref()
+    }
+}
+
+impl<T: PartialEq> Trie<T> {
+    /// Compares the keys and values of both tries, e.g. to sync a copy of `self` with `other`
+    pub fn diff<'a>(&'a self, other: &'a Trie<T>) -> TrieDiff<'a, T> {
+        let mut diff = TrieDiff {
+            added: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+        };
+        // Both iterators are in key order
+        let (mut old, mut new) = (self.iter().peekable(), other.iter().peekable());
+        loop {
+            let order = match (old.peek(), new.peek()) {
+                (Some((old_key, _)), Some((new_key, _))) => old_key.cmp(new_key),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => break,
+            };
+            match order {
+                Ordering::Less => diff.removed.extend(old.next()),
+                Ordering::Greater => diff.added.extend(new.next()),
+                Ordering::Equal => {
+                    let ((key, old_value), (_, new_value)) =
+                        (old.next().unwrap(), new.next().unwrap());
+                    if old_value != new_value {
+                        diff.changed.push((key, old_value, new_value));
+                    }
+                }
+            }
+        }
+        diff
+    }
+
+    /// Returns whether every key of `self` is also in `other` with an equal value
+    pub fn is_subset_of(&self, other: &Trie<T>) -> bool {
+        self.len <= other.len
+            && self
+                .iter()
+                .all(|(key, value)| other.get(&key) == Some(value))
+    }
+}
+
+// Tries are equal when they store the same keys and values, whatever their configuration or the
+// order the keys were inserted in
+impl<T: PartialEq> PartialEq for Trie<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Eq> Eq for Trie<T> {}
+
+// Hashes the sorted key/value list, consistent with `PartialEq`
+impl<T: Hash> Hash for Trie<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut len = 0;
+        for entry in self {
+            entry.hash(state);
+            len += 1;
+        }
+        state.write_usize(len);
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Trie<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Trie")
+            .field("top_level_nodes", &SortedNodes(&self.raw.top_level_nodes))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_single_value() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert value
+        assert!(trie.insert("hello", 99).is_ok());
+
+        // Get value
+        assert_eq!(trie.get("hello"), Some(&99));
+
+        // Delete value
+        assert!(trie.remove("hello").is_ok());
+
+        // Get value
+        assert_eq!(trie.get("hello"), None);
+    }
+
+    #[test]
+    fn insert_multiple_values() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("hell", 2).is_ok());
+        assert!(trie.insert("hel", 3).is_ok());
+        assert!(trie.insert("hey", 4).is_ok());
+        assert!(trie.insert("back", 4).is_ok());
+
+        println!("{:?}", &trie);
+
+        // Get values
+        assert_eq!(trie.get("hello"), Some(&1));
+        assert_eq!(trie.get("hell"), Some(&2));
+        assert_eq!(trie.get("hel"), Some(&3));
+        assert_eq!(trie.get("hey"), Some(&4));
+
+        // Delete value
+        assert!(trie.remove("hello").is_ok());
+        assert!(trie.remove("hello").is_err());
+        assert!(trie.remove("hell").is_ok());
+        assert!(trie.remove("hel").is_ok());
+        println!("{:?}", &trie);
+        assert!(trie.remove("hey").is_ok());
+        println!("{:?}", &trie);
+        assert!(trie.remove("back").is_ok());
+        println!("{:?}", &trie);
+
+        // Get value
+        assert_eq!(trie.get("hello"), None);
+    }
+
+    #[test]
+    fn duplicate_policies() {
+        // Reject is the default
+        let mut trie = Trie::new();
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("hello", 2).is_err());
+        assert_eq!(trie.get("hello"), Some(&1));
+
+        let mut trie = Trie::new().with_policy(DuplicatePolicy::Reject);
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("hello", 2).is_err());
+        assert_eq!(trie.get("hello"), Some(&1));
+
+        let mut trie = Trie::new().with_policy(DuplicatePolicy::Replace);
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("hello", 2).is_ok());
+        assert_eq!(trie.get("hello"), Some(&2));
+
+        let mut trie = Trie::new().with_policy(DuplicatePolicy::KeepFirst);
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("hello", 2).is_ok());
+        assert_eq!(trie.get("hello"), Some(&1));
+    }
+
+    #[test]
+    fn insert_multiple_values_with_same_key() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert multiple values with same key
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("hello", 5).is_err());
+    }
+
+    #[test]
+    fn debug_output_is_deterministic() {
+        // Create two `Trie`s with the same keys inserted in different orders
+        let mut trie = Trie::new();
+        let mut other_trie = Trie::new();
+        let keys = ["hello", "hell", "hey", "back", "bag", "zebra", "apple"];
+        for (value, key) in keys.iter().enumerate() {
+            assert!(trie.insert(key, value).is_ok());
+        }
+        for (value, key) in keys.iter().enumerate().rev() {
+            assert!(other_trie.insert(key, value).is_ok());
+        }
+
+        // Format the same trie twice
+        assert_eq!(format!("{:?}", &trie), format!("{:?}", &trie));
+
+        // Insertion order doesn't matter
+        assert_eq!(format!("{:?}", &trie), format!("{:?}", &other_trie));
+    }
+
+    #[test]
+    fn debug_output_has_no_addresses() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("hel", 2).is_ok());
+        assert!(trie.insert("back", 3).is_ok());
+
+        // Parent pointers are not printed
+        let output = format!("{:?}", &trie);
+        assert!(!output.contains("0x"));
+    }
+
+    #[test]
+    fn level_stats() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        assert!(trie.level_stats().is_empty());
+
+        // Insert values
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("hell", 2).is_ok());
+        assert!(trie.insert("hel", 3).is_ok());
+        assert!(trie.insert("hey", 4).is_ok());
+        assert!(trie.insert("back", 5).is_ok());
+
+        let stats = trie.level_stats();
+        let counts: Vec<_> = stats
+            .iter()
+            .map(|stat| (stat.depth, stat.node_count, stat.value_count))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![(1, 2, 0), (2, 2, 0), (3, 3, 2), (4, 2, 2), (5, 1, 1)]
+        );
+
+        let fanouts: Vec<_> = stats.iter().map(|stat| stat.average_fanout).collect();
+        assert_eq!(fanouts, vec![1.0, 1.5, 2.0 / 3.0, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn remove_if_checks_value() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("positive", 5).is_ok());
+        assert!(trie.insert("negative", -5).is_ok());
+
+        // Only negative values are removed
+        assert!(!trie.remove_if("positive", |value| *value < 0));
+        assert!(trie.remove_if("negative", |value| *value < 0));
+        assert!(!trie.remove_if("negative", |value| *value < 0));
+        assert!(!trie.remove_if("missing", |value| *value < 0));
+
+        // Get values
+        assert_eq!(trie.get("positive"), Some(&5));
+        assert_eq!(trie.get("negative"), None);
+    }
+
+    #[test]
+    fn remove_keeps_sibling_keys() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values sharing a top level node, then enough others to make maps grow
+        assert!(trie.insert("hx", 1).is_ok());
+        assert!(trie.insert("hy", 2).is_ok());
+        for key in ["ha", "hb", "hc", "hd", "a", "b", "c", "d", "e", "f", "g"] {
+            assert!(trie.insert(key, 0).is_ok());
+        }
+
+        // Delete value
+        assert!(trie.remove("hy").is_ok());
+
+        // Get values
+        assert_eq!(trie.get("hx"), Some(&1));
+        assert_eq!(trie.get("hy"), None);
+        assert_eq!(trie.get("ha"), Some(&0));
+    }
+
+    #[test]
+    fn retain_prunes_removed_values() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("hell", 2).is_ok());
+        assert!(trie.insert("hey", 3).is_ok());
+        assert!(trie.insert("back", 4).is_ok());
+
+        // Keep odd values and the keys starting with `he`
+        trie.retain(|key, value| *value % 2 == 1 && key.starts_with("he"));
+
+        // Get values
+        assert_eq!(trie.get("hello"), Some(&1));
+        assert_eq!(trie.get("hell"), None);
+        assert_eq!(trie.get("hey"), Some(&3));
+        assert_eq!(trie.get("back"), None);
+        assert!(!trie.raw.top_level_nodes.contains_key(&'b'));
+    }
+
+    #[test]
+    fn match_longest_prefix() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("in", 1).is_ok());
+        assert!(trie.insert("into", 2).is_ok());
+        assert!(trie.insert("intoxicated", 3).is_ok());
+
+        // Longest matching key
+        assert_eq!(trie.match_longest("intoxicate"), Some((4, &2)));
+        assert_eq!(trie.match_longest("into"), Some((4, &2)));
+        assert_eq!(trie.match_longest("intoxicated!"), Some((11, &3)));
+
+        // `int` is only a structural node, so the shorter valued key wins
+        assert_eq!(trie.match_longest("intern"), Some((2, &1)));
+
+        // No stored key is a prefix
+        assert_eq!(trie.match_longest("i"), None);
+        assert_eq!(trie.match_longest("out"), None);
+        assert_eq!(trie.match_longest(""), None);
+    }
+
+    #[test]
+    fn keys_with_prefix() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("hell", 2).is_ok());
+        assert!(trie.insert("hey", 3).is_ok());
+        assert!(trie.insert("back", 4).is_ok());
+
+        // Keys are returned in lexicographic order
+        assert_eq!(trie.keys_with_prefix("he"), vec!["hell", "hello", "hey"]);
+        assert_eq!(trie.keys_with_prefix("hell"), vec!["hell", "hello"]);
+        assert_eq!(
+            trie.keys_with_prefix(""),
+            vec!["back", "hell", "hello", "hey"]
+        );
+        assert!(trie.keys_with_prefix("x").is_empty());
+
+        assert!(trie.starts_with("hel"));
+        assert!(trie.starts_with(""));
+        assert!(!trie.starts_with("hex"));
+        assert!(trie.contains_key("hell"));
+        assert!(!trie.contains_key("hel"));
+    }
+
+    #[test]
+    fn entry_or_default_counts_words() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Count words
+        for word in "the cat saw the other cat by the door".split(' ') {
+            *trie.entry_or_default(word) += 1;
+        }
+
+        // Get values
+        assert_eq!(trie.get("the"), Some(&3));
+        assert_eq!(trie.get("cat"), Some(&2));
+        assert_eq!(trie.get("other"), Some(&1));
+        assert_eq!(trie.get("th"), None);
+
+        // Existing values are left alone
+        assert_eq!(*trie.get_or_insert_with("door", || 10), 1);
+        assert_eq!(*trie.get_or_insert_with("window", || 10), 10);
+    }
+
+    #[test]
+    fn remove_many_counts_removed_values() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("hell", 2).is_ok());
+        assert!(trie.insert("hey", 3).is_ok());
+        assert!(trie.insert("back", 4).is_ok());
+
+        // Only present keys count, duplicates included
+        assert_eq!(
+            trie.remove_many(&["hello", "hel", "back", "missing", "", "hello"]),
+            2
+        );
+
+        // Get values
+        assert_eq!(trie.get("hello"), None);
+        assert_eq!(trie.get("back"), None);
+        assert_eq!(trie.get("hell"), Some(&2));
+        assert_eq!(trie.get("hey"), Some(&3));
+        assert!(!trie.starts_with("hello"));
+        assert!(!trie.starts_with("b"));
+    }
+
+    #[test]
+    fn walk_visits_nodes_in_order() {
+        #[derive(Default)]
+        struct KeyCollector {
+            keys: Vec<String>,
+            events: Vec<String>,
+        }
+
+        impl TrieVisitor<i32> for KeyCollector {
+            fn enter(&mut self, partial_key: &str, value: Option<&i32>) {
+                if value.is_some() {
+                    self.keys.push(partial_key.to_owned());
+                }
+                self.events.push(format!("+{}", partial_key));
+            }
+
+            fn leave(&mut self, partial_key: &str) {
+                self.events.push(format!("-{}", partial_key));
+            }
+        }
+
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("hey", 1).is_ok());
+        assert!(trie.insert("he", 2).is_ok());
+        assert!(trie.insert("ab", 3).is_ok());
+        assert!(trie.insert("hello", 4).is_ok());
+
+        let mut visitor = KeyCollector::default();
+        trie.walk(&mut visitor);
+
+        assert_eq!(visitor.keys, vec!["ab", "he", "hello", "hey"]);
+        assert_eq!(
+            visitor.events,
+            vec![
+                "+a", "+ab", "-ab", "-a", "+h", "+he", "+hel", "+hell", "+hello", "-hello",
+                "-hell", "-hel", "+hey", "-hey", "-he", "-h",
+            ]
+        );
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn normalization_matches_equivalent_keys() {
+        // Create new `Trie`s
+        let mut plain = Trie::new();
+        let mut trie = Trie::new().with_normalization(Normalization::Nfc);
+
+        // Insert the precomposed form
+        assert!(plain.insert("caf\u{e9}", 1).is_ok());
+        assert!(trie.insert("caf\u{e9}", 1).is_ok());
+
+        // Read and remove via the decomposed form
+        assert_eq!(plain.get("cafe\u{301}"), None);
+        assert_eq!(trie.get("cafe\u{301}"), Some(&1));
+        assert!(trie.insert("cafe\u{301}", 2).is_err());
+        assert!(trie.remove("cafe\u{301}").is_ok());
+        assert_eq!(trie.get("caf\u{e9}"), None);
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn match_longest_normalizes_input() {
+        // Create new `Trie`
+        let mut trie = Trie::new().with_normalization(Normalization::Nfc);
+        assert!(trie.insert("caf\u{e9}", 1).is_ok());
+
+        // The length is counted in the precomposed form
+        assert_eq!(trie.match_longest("cafe\u{301} au lait"), Some((4, &1)));
+        assert_eq!(trie.match_longest("cafe"), None);
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn ancestor_value_normalizes_key() {
+        // Create new `Trie`
+        let mut trie = Trie::new().with_normalization(Normalization::Nfd);
+        assert!(trie.insert("caf\u{e9}", 1).is_ok());
+        assert!(trie.insert("caf\u{e9}.menu", 2).is_ok());
+
+        assert_eq!(trie.ancestor_value("caf\u{e9}.hours"), Some(&1));
+        assert_eq!(trie.ancestor_value("cafe\u{301}.menu"), Some(&2));
+    }
+
+    #[test]
+    fn get_pair_mut_swaps_values() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("hey", 1).is_ok());
+        assert!(trie.insert("hello", 2).is_ok());
+        assert!(trie.insert("he", 3).is_ok());
+        assert!(trie.insert("world", 4).is_ok());
+
+        // Swap values below a shared prefix, along one path and across top level nodes
+        for (a, b) in [("hey", "hello"), ("he", "hello"), ("world", "he")] {
+            let (Some(a), Some(b)) = trie.get_pair_mut(a, b) else {
+                panic!("both keys have values");
+            };
+            std::mem::swap(a, b);
+        }
+        assert_eq!(trie.get("hey"), Some(&2));
+        assert_eq!(trie.get("hello"), Some(&3));
+        assert_eq!(trie.get("he"), Some(&4));
+        assert_eq!(trie.get("world"), Some(&1));
+
+        // The same key is only borrowed once
+        assert_eq!(trie.get_pair_mut("hey", "hey"), (Some(&mut 2), None));
+        assert_eq!(trie.get_pair_mut("hey", "hex"), (Some(&mut 2), None));
+        assert_eq!(trie.get_pair_mut("h", "world"), (None, Some(&mut 1)));
+    }
+
+    #[test]
+    fn write_to_read_from_round_trip() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("hey", "a".to_owned()).is_ok());
+        assert!(trie.insert("hello", "b".to_owned()).is_ok());
+        assert!(trie.insert("h\u{e9}", String::new()).is_ok());
+
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        assert!(trie.write_to(&mut cursor).is_ok());
+
+        cursor.set_position(0);
+        let restored = Trie::<String>::read_from(&mut cursor).unwrap();
+        assert_eq!(format!("{:?}", restored), format!("{:?}", trie));
+
+        // A truncated stream is an error
+        let bytes = cursor.into_inner();
+        let truncated = &mut &bytes[..bytes.len() - 1];
+        assert!(Trie::<String>::read_from(truncated).is_err());
+    }
+
+    #[test]
+    fn into_keys_and_values() {
+        fn new_trie() -> Trie<i32> {
+            // Create new `Trie`
+            let mut trie = Trie::new();
+
+            // Insert values
+            assert!(trie.insert("hey", 1).is_ok());
+            assert!(trie.insert("he", 2).is_ok());
+            assert!(trie.insert("world", 3).is_ok());
+            assert!(trie.insert("hello", 4).is_ok());
+            trie
+        }
+
+        let mut keys: Vec<_> = new_trie().into_keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["he", "hello", "hey", "world"]);
+
+        let mut values: Vec<_> = new_trie().into_values().collect();
+        values.sort();
+        assert_eq!(values, vec![1, 2, 3, 4]);
+
+        // Keys are yielded in lexicographic order
+        let pairs: Vec<_> = new_trie().into_iter().collect();
+        assert_eq!(
+            pairs,
+            vec![
+                ("he".to_owned(), 2),
+                ("hello".to_owned(), 4),
+                ("hey".to_owned(), 1),
+                ("world".to_owned(), 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn swap_value_returns_old_value() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Absent keys are inserted
+        assert_eq!(trie.swap_value("hello", 1), None);
+        assert_eq!(trie.swap_value("hello", 2), Some(1));
+        assert_eq!(trie.swap_value("hell", 3), None);
+
+        // Get values
+        assert_eq!(trie.get("hello"), Some(&2));
+        assert_eq!(trie.get("hell"), Some(&3));
+    }
+
+    #[test]
+    fn max_key_len_rejects_long_keys() {
+        // Create new `Trie`
+        let mut trie = Trie::new().with_max_key_len(5);
+
+        // The bound counts chars, not bytes
+        assert!(trie.insert("h\u{e9}llo", 1).is_ok());
+        assert_eq!(
+            trie.insert("hellos", 2),
+            Err(TrieError::KeyTooLong { len: 6, max: 5 })
+        );
+        assert_eq!(trie.insert("", 3), Err(TrieError::EmptyKey));
+        assert!(!trie.starts_with("hello"));
+    }
+
+    #[test]
+    fn max_key_len_applies_to_every_insert() {
+        // Create new `Trie`
+        let mut trie = Trie::new().with_max_key_len(5);
+        let too_long = TrieError::KeyTooLong { len: 6, max: 5 };
+
+        assert_eq!(trie.try_get_or_insert_with("hellos", || 1), Err(too_long));
+        assert_eq!(trie.try_swap_value("hellos", 1), Err(too_long));
+        assert!(matches!(trie.try_entry("hellos"), Err(error) if error == too_long));
+        assert_eq!(trie.try_entry_or_default("hellos"), Err(too_long));
+        assert_eq!(trie.insert_cursor().insert("hellos", 1), Err(too_long));
+        assert!(trie.is_empty());
+        assert_eq!(trie.node_count(), 0);
+
+        // Keys within the bound still work
+        assert_eq!(trie.try_swap_value("hello", 1), Ok(None));
+        assert!(matches!(trie.try_entry("hello"), Ok(Entry::Occupied(_))));
+    }
+
+    #[test]
+    #[should_panic(expected = "Key has 6 chars, more than the maximum of 5")]
+    fn entry_panics_on_keys_over_max_key_len() {
+        // Create new `Trie`
+        let mut trie: Trie<i32> = Trie::new().with_max_key_len(5);
+
+        trie.entry("hellos").or_insert(1);
+    }
+
+    #[test]
+    fn drain_prefix_moves_values_out() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("hey", 1).is_ok());
+        assert!(trie.insert("hello", 2).is_ok());
+        assert!(trie.insert("h", 3).is_ok());
+        assert!(trie.insert("world", 4).is_ok());
+
+        assert_eq!(
+            trie.drain_prefix("he"),
+            vec![("hello".to_owned(), 2), ("hey".to_owned(), 1)]
+        );
+        assert!(trie.drain_prefix("he").is_empty());
+        assert!(!trie.starts_with("he"));
+        assert_eq!(trie.get("h"), Some(&3));
+        assert_eq!(trie.get("world"), Some(&4));
+
+        // Remove the rest
+        assert_eq!(trie.remove_prefix("wor"), 1);
+        assert!(!trie.starts_with("w"));
+        assert_eq!(trie.remove_prefix(""), 1);
+        assert!(!trie.starts_with(""));
+    }
+
+    #[test]
+    fn auto_shrink_releases_capacity() {
+        fn child_capacity(trie: &Trie<u32>) -> usize {
+            trie.raw.top_level_nodes[&'a'].child_nodes.capacity()
+        }
+
+        // Create new `Trie`
+        let mut trie = Trie::new().with_auto_shrink(true);
+
+        // Insert values
+        let keys: Vec<_> = ('b'..='z')
+            .chain('A'..='Z')
+            .map(|char| format!("a{}", char))
+            .collect();
+        for (value, key) in keys.iter().enumerate() {
+            assert!(trie.insert(key, value as u32).is_ok());
+        }
+        assert!(child_capacity(&trie) >= keys.len());
+
+        let keys: Vec<_> = keys.iter().skip(2).map(String::as_str).collect();
+        assert_eq!(trie.remove_many(&keys), keys.len());
+        assert!(child_capacity(&trie) < 8);
+
+        trie.retain(|_, value| *value == 0);
+        assert_eq!(trie.keys_with_prefix(""), vec!["ab"]);
+        assert!(child_capacity(&trie) < 4);
+    }
+
+    #[test]
+    fn value_at_interior_nodes() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("hel", 1).is_ok());
+        assert!(trie.insert("hello", 2).is_ok());
+
+        // Interior nodes with a value return it
+        assert_eq!(trie.get("hel"), Some(&1));
+        assert_eq!(trie.value_at("hel"), Some(&1));
+        assert_eq!(trie.value_at("hello"), Some(&2));
+
+        // Structural nodes and unknown paths don't
+        assert_eq!(trie.get("he"), None);
+        assert_eq!(trie.value_at("he"), None);
+        assert_eq!(trie.value_at("help"), None);
+        assert_eq!(trie.value_at(""), None);
+    }
+
+    #[cfg(feature = "insertion-order")]
+    #[test]
+    fn keys_by_insertion_replays_insert_order() {
+        // Create new `Trie`
+        let mut trie = Trie::new().with_policy(DuplicatePolicy::Replace);
+
+        // Insert values in scrambled order
+        let keys = ["world", "he", "hello", "a", "hey", "wor"];
+        for key in keys {
+            assert!(trie.insert(key, 0).is_ok());
+        }
+        assert_eq!(trie.keys_by_insertion(), keys);
+
+        // Replacing keeps the position, reinserting moves to the end
+        assert!(trie.insert("he", 1).is_ok());
+        assert!(trie.remove("hello").is_ok());
+        *trie.entry_or_default("hello") += 1;
+        assert_eq!(
+            trie.keys_by_insertion(),
+            ["world", "he", "a", "hey", "wor", "hello"]
+        );
+    }
+
+    #[test]
+    fn equal_tries_hash_equally() {
+        use std::collections::{hash_map::DefaultHasher, HashSet};
+
+        fn hash_of(trie: &Trie<i32>) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            trie.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        // Create new `Trie`s with the same entries in different orders
+        let mut a = Trie::new();
+        assert!(a.insert("hey", 1).is_ok());
+        assert!(a.insert("hello", 2).is_ok());
+
+        let mut b = Trie::new();
+        assert!(b.insert("removed", 0).is_ok());
+        assert!(b.insert("hello", 2).is_ok());
+        assert!(b.insert("hey", 1).is_ok());
+        assert!(b.remove("removed").is_ok());
+
+        assert_eq!(a, b);
+        assert_eq!(hash_of(&a), hash_of(&b));
+
+        // A different value makes them differ
+        let mut c = Trie::new();
+        assert!(c.insert("hey", 1).is_ok());
+        assert!(c.insert("hello", 3).is_ok());
+        assert_ne!(a, c);
+        assert_ne!(hash_of(&a), hash_of(&c));
+
+        assert_eq!(HashSet::from([a, b, c]).len(), 2);
+    }
+
+    #[test]
+    fn boundaries_report_continuations() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("hey", 1).is_ok());
+        assert!(trie.insert("he", 2).is_ok());
+        assert!(trie.insert("hel", 3).is_ok());
+
+        assert_eq!(
+            trie.boundaries(),
+            vec![
+                ("he".to_owned(), vec!['l', 'y']),
+                ("hel".to_owned(), vec![]),
+                ("hey".to_owned(), vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn clear_values_keeps_structure() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("hey", 1).is_ok());
+        assert!(trie.insert("he", 2).is_ok());
+        assert!(trie.insert("world", 3).is_ok());
+        assert_eq!(trie.len(), 3);
+        let node_count = trie.node_count();
+
+        trie.clear_values();
+        assert_eq!(trie.len(), 0);
+        assert_eq!(trie.node_count(), node_count);
+        assert_eq!(trie.get("he"), None);
+        assert!(!trie.starts_with("wor"));
+        assert!(!trie.starts_with(""));
+
+        // The structure is reused
+        assert!(trie.insert("hey", 4).is_ok());
+        assert_eq!(trie.len(), 1);
+        assert_eq!(trie.node_count(), node_count);
+        assert!(trie.starts_with("he"));
+        assert!(!trie.starts_with("w"));
+    }
+
+    #[test]
+    fn is_subset_of() {
+        // Create new `Trie`s
+        let mut old = Trie::new();
+        assert!(old.insert("hey", 1).is_ok());
+        assert!(old.insert("he", 2).is_ok());
+
+        let mut new = Trie::new();
+        assert!(new.insert("he", 2).is_ok());
+        assert!(new.insert("hey", 1).is_ok());
+        assert!(new.insert("hello", 3).is_ok());
+
+        assert!(old.is_subset_of(&new));
+        assert!(!new.is_subset_of(&old));
+        assert!(Trie::new().is_subset_of(&old));
+
+        // A differing value is not contained
+        assert!(new.swap_value("hey", 4).is_some());
+        assert!(!old.is_subset_of(&new));
+    }
+
+    #[test]
+    fn entry_hashes_each_char_once() {
+        use crate::trie_core::hash_counting::hash_count;
+
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("hello", 1).is_ok());
+
+        // Diverging below an existing path
+        let before = hash_count();
+        *trie.entry("help").or_insert(0) += 2;
+        assert!(hash_count() - before <= "help".len());
+
+        // Filling in a structural node
+        let before = hash_count();
+        assert_eq!(*trie.entry("hel").or_insert(3), 3);
+        assert!(hash_count() - before <= "hel".len());
+
+        // Updating an existing value
+        let before = hash_count();
+        trie.entry("hello")
+            .and_modify(|value| *value += 10)
+            .or_insert(0);
+        assert!(hash_count() - before <= "hello".len());
+
+        assert_eq!(trie.get("help"), Some(&2));
+        assert_eq!(trie.get("hello"), Some(&11));
+        assert_eq!(trie.len(), 3);
+
+        match trie.entry("hel") {
+            Entry::Occupied(mut entry) => assert_eq!(entry.insert(4), 3),
+            Entry::Vacant(_) => panic!("\"hel\" has a value"),
+        }
+        assert_eq!(trie.get("hel"), Some(&4));
+    }
+
+    #[test]
+    fn iter_prefix_is_lazy() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        let letters = 'a'..='z';
+        for first in letters.clone() {
+            for second in letters.clone() {
+                assert!(trie.insert(&format!("a{}{}", first, second), 0).is_ok());
+            }
+        }
+
+        let mut iter = trie.iter_prefix("a");
+        let keys: Vec<_> = iter.by_ref().take(3).map(|(key, _)| key).collect();
+        assert_eq!(keys, vec!["aaa", "aab", "aac"]);
+
+        // Only the siblings along the path to "aac" were expanded
+        assert!(iter.stack.len() < 2 * 26);
+        assert_eq!(iter.count(), 26 * 26 - 3);
+        assert_eq!(trie.iter_prefix("b").count(), 0);
+    }
+
+    #[test]
+    fn value_count_matches_len() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        for key in ["hello", "help", "hel", "world", "word"] {
+            assert!(trie.insert(key, 1).is_ok());
+        }
+        assert!(trie.remove("help").is_ok());
+        assert!(trie.remove("hel").is_ok());
+        assert!(trie.remove("nothing").is_err());
+        trie.remove_many(&["word", "world"]);
+        assert!(trie.insert("hat", 1).is_ok());
+
+        assert_eq!(trie.value_count(), trie.len());
+        assert_eq!(trie.value_count(), 2);
+        // "h", "e", "l", "l", "o", "a", "t"
+        assert_eq!(trie.node_count(), 7);
+    }
+
+    #[test]
+    fn nearest_key() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        assert_eq!(trie.nearest_key("hello"), None);
+
+        for key in ["hello", "help", "hat"] {
+            assert!(trie.insert(key, 1).is_ok());
+        }
+        assert_eq!(trie.nearest_key("helm").as_deref(), Some("help"));
+        assert_eq!(trie.nearest_key("helq").as_deref(), Some("help"));
+        assert_eq!(trie.nearest_key("help").as_deref(), Some("help"));
+        assert_eq!(trie.nearest_key("hel").as_deref(), Some("hello"));
+        assert_eq!(trie.nearest_key("hb").as_deref(), Some("hello"));
+        assert_eq!(trie.nearest_key("hz").as_deref(), Some("help"));
+        assert_eq!(trie.nearest_key("world").as_deref(), Some("help"));
+        assert_eq!(trie.nearest_key("").as_deref(), Some("hat"));
+
+        // Keys that are a prefix of `query` count as ordered before it
+        assert_eq!(trie.nearest_key("hats").as_deref(), Some("hat"));
+        assert!(trie.insert("he", 1).is_ok());
+        assert_eq!(trie.nearest_key("hex").as_deref(), Some("help"));
+    }
+
+    #[test]
+    fn removing_interior_value_keeps_descendants() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        assert!(trie.insert("hel", 1).is_ok());
+        assert!(trie.insert("hello", 2).is_ok());
+
+        assert!(trie.remove("hel").is_ok());
+        assert_eq!(trie.get("hel"), None);
+        assert_eq!(trie.get("hello"), Some(&2));
+        assert_eq!(trie.len(), 1);
+
+        assert!(trie.insert("hel", 1).is_ok());
+        assert_eq!(
+            trie.remove_and_promote("hel"),
+            Ok(Some(String::from("hello")))
+        );
+        assert_eq!(trie.get("hello"), Some(&2));
+        assert_eq!(trie.len(), 1);
+
+        // Leaves and branching nodes have nothing to promote
+        assert!(trie.insert("help", 3).is_ok());
+        assert!(trie.insert("hel", 1).is_ok());
+        assert_eq!(trie.remove_and_promote("hel"), Ok(None));
+        assert_eq!(trie.remove_and_promote("help"), Ok(None));
+        assert_eq!(trie.remove_and_promote("help"), Err(TrieError::KeyNotFound));
+        assert_eq!(trie.remove_and_promote(""), Err(TrieError::EmptyKey));
+        assert_eq!(trie.get("hello"), Some(&2));
+        assert_eq!(trie.len(), 1);
+    }
+
+    #[test]
+    fn distinct_prefix_count() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        assert_eq!(trie.distinct_prefix_count(), 0);
+
+        for key in ["hello", "help", "hat", "world"] {
+            assert!(trie.insert(key, 1).is_ok());
+        }
+        // Keys branch after "h" and "hel"
+        assert_eq!(trie.distinct_prefix_count(), 2);
+
+        assert!(trie.insert("helping", 1).is_ok());
+        assert_eq!(trie.distinct_prefix_count(), 2);
+        assert!(trie.insert("helps", 1).is_ok());
+        assert_eq!(trie.distinct_prefix_count(), 3);
+    }
+
+    #[test]
+    fn ancestor_value_falls_back_to_prefixes() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        assert!(trie.insert("a", 1).is_ok());
+        assert!(trie.insert("a.b", 2).is_ok());
+        assert!(trie.insert("a.b.c.d", 4).is_ok());
+
+        assert_eq!(trie.ancestor_value("a.b.c"), Some(&2));
+        assert_eq!(trie.ancestor_value("a.b"), Some(&2));
+        assert_eq!(trie.ancestor_value("a.x"), Some(&1));
+        assert_eq!(trie.ancestor_value("a.b.c.d.e"), Some(&4));
+        assert_eq!(trie.ancestor_value("b"), None);
+        assert_eq!(trie.ancestor_value(""), None);
+    }
+
+    #[test]
+    fn very_long_keys_do_not_overflow_the_stack() {
+        let key = "a".repeat(1_000_000);
+
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        assert!(trie.insert(&key, 1).is_ok());
+        assert!(trie.insert(&key[..500_000], 2).is_ok());
+        assert_eq!(trie.get(&key), Some(&1));
+        assert!(trie.contains_key(&key));
+
+        assert!(trie.remove(&key).is_ok());
+        assert!(!trie.contains_key(&key));
+        assert_eq!(trie.get(&key[..500_000]), Some(&2));
+
+        assert!(trie.insert(&key, 1).is_ok());
+        trie.retain(|_, value| *value == 1);
+        assert_eq!(trie.remove_many(&[&key]), 1);
+        assert!(trie.is_empty());
+
+        // Dropping the nodes must not recurse either
+        assert!(trie.insert(&key, 1).is_ok());
+        drop(trie);
+    }
+
+    #[test]
+    fn set_existing_only_updates() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        assert!(trie.insert("hello", 1).is_ok());
+
+        assert_eq!(trie.set_existing("hello", 2), Ok(1));
+        assert_eq!(trie.get("hello"), Some(&2));
+
+        assert_eq!(trie.set_existing("hell", 3), Err(TrieError::KeyNotFound));
+        assert_eq!(trie.set_existing("world", 3), Err(TrieError::KeyNotFound));
+        assert_eq!(trie.set_existing("", 3), Err(TrieError::KeyNotFound));
+        assert_eq!(trie.len(), 1);
+        assert_eq!(trie.node_count(), 5);
+    }
+
+    #[test]
+    fn insert_cursor_skips_shared_prefixes() {
+        use crate::trie_core::hash_counting::hash_count;
+
+        let prefix = "/usr/share/doc/packages/".repeat(8);
+        let keys: Vec<_> = (0..1000).map(|i| format!("{}{:04}", prefix, i)).collect();
+
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        let start = hash_count();
+        for key in &keys {
+            assert!(trie.insert(key, 1).is_ok());
+        }
+        let walk_hashes = hash_count() - start;
+
+        let mut cursor_trie = Trie::new();
+        let start = hash_count();
+        {
+            let mut cursor = cursor_trie.insert_cursor();
+            for key in &keys {
+                assert!(cursor.insert(key, 1).is_ok());
+            }
+            assert_eq!(cursor.insert(&keys[0], 2), Err(TrieError::DuplicateKey));
+            assert_eq!(cursor.insert("", 2), Err(TrieError::EmptyKey));
+        }
+        let cursor_hashes = hash_count() - start;
+
+        assert!(cursor_hashes * 20 < walk_hashes);
+        assert_eq!(cursor_trie, trie);
+        assert_eq!(cursor_trie.len(), 1000);
+        assert_eq!(cursor_trie.node_count(), trie.node_count());
+    }
+
+    #[test]
+    fn fold_prefix_sums_values() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        for (key, value) in [
+            ("error.io", 3),
+            ("error.parse", 4),
+            ("errors", 100),
+            ("info", 5),
+        ] {
+            assert!(trie.insert(key, value).is_ok());
+        }
+
+        assert_eq!(
+            trie.fold_prefix("error.", 0, |sum, _, value| sum + value),
+            7
+        );
+        assert_eq!(trie.fold_prefix("", 0, |sum, _, value| sum + value), 112);
+        assert_eq!(trie.fold_prefix("warn", 0, |sum, _, value| sum + value), 0);
+        assert_eq!(
+            trie.fold_prefix("error", String::new(), |keys, key, _| keys + key + " "),
+            "error.io error.parse errors "
+        );
+    }
+
+    #[test]
+    fn terminal_only_rejects_prefix_conflicts() {
+        // Create new `Trie`
+        let mut trie = Trie::new().with_terminal_only(true);
+        assert!(trie.insert("hell", 1).is_ok());
+        assert_eq!(trie.insert("hello", 2), Err(TrieError::PrefixConflict));
+
+        assert!(trie.remove("hell").is_ok());
+        assert!(trie.insert("hello", 2).is_ok());
+        assert_eq!(trie.insert("hell", 1), Err(TrieError::PrefixConflict));
+        assert_eq!(trie.insert("h", 1), Err(TrieError::PrefixConflict));
+
+        // Siblings and duplicates are not conflicts
+        assert!(trie.insert("help", 3).is_ok());
+        assert_eq!(trie.insert("hello", 4), Err(TrieError::DuplicateKey));
+        assert_eq!(trie.len(), 2);
+        assert_eq!(trie.get("hell"), None);
+
+        // Keys may still be prefixes of each other by default
+        let mut trie = Trie::new();
+        assert!(trie.insert("hell", 1).is_ok());
+        assert!(trie.insert("hello", 2).is_ok());
+    }
+
+    #[test]
+    fn terminal_only_applies_to_every_insert() {
+        let conflict = TrieError::PrefixConflict;
+        let new_trie = || {
+            let mut trie = Trie::new().with_terminal_only(true);
+            assert!(trie.insert("hell", 1).is_ok());
+            trie
+        };
+
+        let mut trie = new_trie();
+        assert_eq!(trie.try_get_or_insert_with("hello", || 2), Err(conflict));
+        assert_eq!(trie.try_get_or_insert_with("he", || 2), Err(conflict));
+        assert_eq!(trie.try_get_or_insert_with("hell", || 2), Ok(&mut 1));
+
+        let mut trie = new_trie();
+        assert_eq!(trie.try_swap_value("hello", 2), Err(conflict));
+        assert_eq!(trie.try_swap_value("he", 2), Err(conflict));
+        assert_eq!(trie.try_swap_value("hell", 2), Ok(Some(1)));
+
+        let mut trie = new_trie();
+        assert!(matches!(trie.try_entry("hello"), Err(error) if error == conflict));
+        assert!(matches!(trie.try_entry("he"), Err(error) if error == conflict));
+        assert_eq!(trie.try_entry_or_default("hello"), Err(conflict));
+        assert!(matches!(trie.try_entry("help"), Ok(Entry::Vacant(_))));
+
+        // The cursor checks the nodes it took out of the trie too
+        let mut trie = new_trie();
+        let mut cursor = trie.insert_cursor();
+        assert_eq!(cursor.insert("hello", 2), Err(conflict));
+        assert!(cursor.insert("help", 2).is_ok());
+        assert_eq!(cursor.insert("helpful", 3), Err(conflict));
+        assert!(cursor.insert("world", 4).is_ok());
+        assert_eq!(cursor.insert("wor", 5), Err(conflict));
+        assert_eq!(cursor.insert("hel", 5), Err(conflict));
+        drop(cursor);
+        assert_eq!(trie.len(), 3);
+
+        let mut trie = new_trie();
+        let mut pairs = vec![("hello".to_string(), 2), ("a".to_string(), 3)];
+        assert_eq!(
+            trie.insert_sorted_dedup(&mut pairs, |a, b| *a += b),
+            Err(("hello".to_string(), conflict))
+        );
+        assert_eq!(trie.get("a"), Some(&3));
+        assert_eq!(trie.get("hello"), None);
+        assert_eq!(trie.len(), 2);
+    }
+
+    #[test]
+    fn edge_histogram_counts_chars() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        assert!(trie.edge_histogram().is_empty());
+
+        for key in ["hello", "help", "lol"] {
+            assert!(trie.insert(key, 1).is_ok());
+        }
+        let histogram = trie.edge_histogram();
+
+        // "hel" is shared by "hello" and "help"
+        assert_eq!(histogram[&'h'], 1);
+        assert_eq!(histogram[&'e'], 1);
+        assert_eq!(histogram[&'l'], 4);
+        assert_eq!(histogram[&'o'], 2);
+        assert_eq!(histogram[&'p'], 1);
+        assert_eq!(histogram.values().sum::<usize>(), trie.node_count());
+    }
+
+    #[test]
+    fn remove_report_counts_pruned_nodes() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        for key in ["hello", "help", "hel", "world"] {
+            assert!(trie.insert(key, 1).is_ok());
+        }
+
+        let report = trie.remove_report("world");
+        assert_eq!(
+            report,
+            Ok(RemoveReport {
+                value: 1,
+                pruned_nodes: 5
+            })
+        );
+        assert_eq!(trie.node_count(), 6);
+
+        // Only the nodes below the last branch or value are freed
+        assert_eq!(
+            trie.remove_report("hello")
+                .map(|report| report.pruned_nodes),
+            Ok(2)
+        );
+        assert_eq!(
+            trie.remove_report("hel").map(|report| report.pruned_nodes),
+            Ok(0)
+        );
+        assert_eq!(
+            trie.remove_report("help").map(|report| report.pruned_nodes),
+            Ok(4)
+        );
+        assert_eq!(trie.node_count(), 0);
+
+        assert_eq!(trie.remove_report("help"), Err(TrieError::KeyNotFound));
+        assert_eq!(trie.remove_report(""), Err(TrieError::EmptyKey));
+        assert!(trie.is_empty());
+    }
+
+    #[test]
+    fn any_trie_downcasts_values() {
+        // Create new `Trie`
+        let mut trie = AnyTrie::new();
+        assert!(trie.insert_boxed("answer", Box::new(42_u32)).is_ok());
+        assert!(trie
+            .insert_boxed("name", Box::new(String::from("trie")))
+            .is_ok());
+
+        assert_eq!(trie.get_downcast::<u32>("answer"), Some(&42));
+        assert_eq!(
+            trie.get_downcast::<String>("name").map(String::as_str),
+            Some("trie")
+        );
+
+        // Wrong types and missing keys both give `None`
+        assert_eq!(trie.get_downcast::<String>("answer"), None);
+        assert_eq!(trie.get_downcast::<u32>("nothing"), None);
+    }
+
+    #[test]
+    fn rename_prefix_moves_subtrees() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        for (key, value) in [("hello", 1), ("help", 2), ("he", 3), ("hat", 4)] {
+            assert!(trie.insert(key, value).is_ok());
+        }
+
+        assert!(trie.rename_prefix("he", "ya").is_ok());
+        assert_eq!(trie.get("yallo"), Some(&1));
+        assert_eq!(trie.get("yalp"), Some(&2));
+        assert_eq!(trie.get("ya"), Some(&3));
+        assert_eq!(trie.get("hello"), None);
+        assert_eq!(trie.keys_with_prefix("h"), vec!["hat"]);
+        assert_eq!(trie.len(), 4);
+        assert!(trie.rename_prefix("nothing", "ya").is_ok());
+
+        // Moving onto existing keys
+        assert!(trie.insert("yell", 5).is_ok());
+        assert_eq!(trie.rename_prefix("yell", "yall"), Ok(()));
+        assert_eq!(
+            trie.rename_prefix("yalp", "hat"),
+            Err(TrieError::DuplicateKey)
+        );
+        assert_eq!(trie.get("hat"), Some(&4));
+        assert_eq!(
+            trie.keys_with_prefix("y"),
+            vec!["ya", "yall", "yallo", "yalp"]
+        );
+        assert_eq!(trie.len(), 5);
+
+        let mut trie = Trie::new().with_policy(DuplicatePolicy::Replace);
+        for (key, value) in [("a/x", 1), ("a/y", 2), ("b/y", 3)] {
+            assert!(trie.insert(key, value).is_ok());
+        }
+        assert!(trie.rename_prefix("a/", "b/").is_ok());
+        assert_eq!(trie.keys_with_prefix(""), vec!["b/x", "b/y"]);
+        assert_eq!(trie.get("b/y"), Some(&2));
+        assert_eq!(trie.len(), 2);
+    }
+
+    #[test]
+    fn get_with_meta_describes_node() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        for key in ["hel", "hello", "help", "world"] {
+            assert!(trie.insert(key, 1).is_ok());
+        }
+
+        let meta = |child_count, is_branch| NodeMeta {
+            child_count,
+            is_branch,
+        };
+        assert_eq!(trie.get_with_meta("hel"), Some((&1, meta(2, true))));
+        assert_eq!(trie.get_with_meta("hello"), Some((&1, meta(0, false))));
+        assert_eq!(trie.get_with_meta("he"), None);
+        assert_eq!(trie.get_with_meta("world"), Some((&1, meta(0, false))));
+
+        assert!(trie.insert("worl", 1).is_ok());
+        assert_eq!(trie.get_with_meta("worl"), Some((&1, meta(1, false))));
+    }
+
+    #[test]
+    fn longest_shared_path() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        for key in ["hello", "help"] {
+            assert!(trie.insert(key, 1).is_ok());
+        }
+
+        // The queries diverge from each other
+        assert_eq!(trie.longest_shared_path("helium", "helper"), 3);
+        // The queries agree but the trie ends
+        assert_eq!(trie.longest_shared_path("helpful", "helpless"), 4);
+        assert_eq!(trie.longest_shared_path("hex", "hex"), 2);
+        assert_eq!(trie.longest_shared_path("hello", "hello"), 5);
+        assert_eq!(trie.longest_shared_path("world", "world"), 0);
+        assert_eq!(trie.longest_shared_path("", "hello"), 0);
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn keys_matching_regex() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        for key in [
+            "hello", "hallo", "helo", "hero", "help", "ohello", "héllo", "hellos",
+        ] {
+            assert!(trie.insert(key, 1).is_ok());
+        }
+
+        let pattern = Regex::new("^h.l+o$").unwrap();
+        assert_eq!(
+            trie.keys_matching(&pattern),
+            vec!["hallo", "hello", "helo", "héllo"]
+        );
+
+        // Unanchored patterns match anywhere in the key
+        let pattern = Regex::new("ell").unwrap();
+        assert_eq!(
+            trie.keys_matching(&pattern),
+            vec!["hello", "hellos", "ohello"]
+        );
+        let pattern = Regex::new("^x").unwrap();
+        assert!(trie.keys_matching(&pattern).is_empty());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn keys_matching_before_a_multibyte_char() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        for key in ["abé", "abc", "xabé", "ab", "aé"] {
+            assert!(trie.insert(key, 1).is_ok());
+        }
+
+        // The match is only reported on the first byte of the char after it
+        let pattern = Regex::new("^ab").unwrap();
+        assert_eq!(trie.keys_matching(&pattern), vec!["ab", "abc", "abé"]);
+        let pattern = Regex::new("ab").unwrap();
+        assert_eq!(
+            trie.keys_matching(&pattern),
+            vec!["ab", "abc", "abé", "xabé"]
+        );
+        let pattern = Regex::new("é$").unwrap();
+        assert_eq!(trie.keys_matching(&pattern), vec!["abé", "aé", "xabé"]);
+    }
+
+    #[test]
+    fn for_each_prefix_mut_updates_values() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        for (key, value) in [("a", 1), ("ab", 2), ("abc", 3), ("b", 4), ("ba", 5)] {
+            assert!(trie.insert(key, value).is_ok());
+        }
+
+        let mut keys = Vec::new();
+        trie.for_each_prefix_mut("a", |key, value| {
+            keys.push(key.to_owned());
+            *value *= 2;
+        });
+        assert_eq!(keys, vec!["a", "ab", "abc"]);
+        assert_eq!(
+            trie.iter().collect::<Vec<_>>(),
+            vec![
+                (String::from("a"), &2),
+                (String::from("ab"), &4),
+                (String::from("abc"), &6),
+                (String::from("b"), &4),
+                (String::from("ba"), &5),
+            ]
+        );
+
+        trie.for_each_prefix_mut("", |_, value| *value += 1);
+        assert_eq!(trie.get("ba"), Some(&6));
+        trie.for_each_prefix_mut("c", |_, _| unreachable!());
+    }
+
+    #[test]
+    fn max_fanout_limits_children() {
+        // Create new `Trie`
+        let mut trie = Trie::new().with_max_fanout(2);
+        assert!(trie.insert("a", 1).is_ok());
+        assert!(trie.insert("b", 2).is_ok());
+        assert_eq!(trie.insert("c", 3), Err(TrieError::FanoutExceeded));
+
+        assert!(trie.insert("ax", 4).is_ok());
+        assert!(trie.insert("ay", 5).is_ok());
+        assert_eq!(trie.insert("az", 6), Err(TrieError::FanoutExceeded));
+        assert_eq!(trie.insert("azzz", 6), Err(TrieError::FanoutExceeded));
+        assert_eq!(trie.node_count(), 4);
+
+        // Existing children can still be reused
+        assert!(trie.insert("axe", 7).is_ok());
+        assert_eq!(trie.len(), 5);
+    }
+
+    #[test]
+    fn max_fanout_applies_to_every_insert() {
+        let exceeded = TrieError::FanoutExceeded;
+        let new_trie = || {
+            let mut trie = Trie::new().with_max_fanout(2);
+            for key in ["a", "b", "ax", "ay"] {
+                assert!(trie.insert(key, 1).is_ok());
+            }
+            trie
+        };
+
+        let mut trie = new_trie();
+        assert_eq!(trie.try_get_or_insert_with("c", || 2), Err(exceeded));
+        assert_eq!(trie.try_swap_value("az", 2), Err(exceeded));
+        assert!(matches!(trie.try_entry("azz"), Err(error) if error == exceeded));
+        assert_eq!(trie.try_entry_or_default("c"), Err(exceeded));
+        assert_eq!(trie.node_count(), 4);
+        assert!(matches!(trie.try_entry("axe"), Ok(Entry::Vacant(_))));
+
+        // The cursor checks the children of the nodes it took out of the trie too
+        let mut trie = new_trie();
+        let mut cursor = trie.insert_cursor();
+        assert!(cursor.insert("axe", 2).is_ok());
+        assert_eq!(cursor.insert("az", 3), Err(exceeded));
+        assert!(cursor.insert("axf", 3).is_ok());
+        assert_eq!(cursor.insert("axg", 4), Err(exceeded));
+        assert_eq!(cursor.insert("c", 4), Err(exceeded));
+        drop(cursor);
+        assert_eq!(trie.node_count(), 6);
+        assert_eq!(trie.len(), 6);
+
+        let mut trie = new_trie();
+        let mut pairs = vec![("az".to_string(), 2)];
+        assert_eq!(
+            trie.insert_sorted_dedup(&mut pairs, |a, b| *a += b),
+            Err(("az".to_string(), exceeded))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "A node can not have any more children")]
+    fn entry_panics_past_max_fanout() {
+        // Create new `Trie`
+        let mut trie = Trie::new().with_max_fanout(1);
+        assert!(trie.insert("a", 1).is_ok());
+
+        *trie.entry_or_default("b") += 1;
+    }
+
+    #[test]
+    fn diff_finds_changes() {
+        // Create new `Trie`s
+        let mut old = Trie::new();
+        for (key, value) in [("hello", 1), ("help", 2), ("world", 3)] {
+            assert!(old.insert(key, value).is_ok());
+        }
+        let mut new = Trie::new();
+        for (key, value) in [("hello", 1), ("hel", 4), ("world", 5)] {
+            assert!(new.insert(key, value).is_ok());
+        }
+
+        assert_eq!(
+            old.diff(&new),
+            TrieDiff {
+                added: vec![(String::from("hel"), &4)],
+                removed: vec![(String::from("help"), &2)],
+                changed: vec![(String::from("world"), &3, &5)],
+            }
+        );
+
+        let diff = old.diff(&old);
+        assert!(diff.added.is_empty() && diff.removed.is_empty() && diff.changed.is_empty());
+    }
+
+    #[test]
+    fn apply_patch() {
+        // Create new `Trie`s
+        let mut old = Trie::new();
+        for (key, value) in [("hello", 1), ("help", 2), ("world", 3)] {
+            assert!(old.insert(key, value).is_ok());
+        }
+        let mut new = Trie::new();
+        for (key, value) in [("hello", 1), ("hel", 4), ("world", 5)] {
+            assert!(new.insert(key, value).is_ok());
+        }
+
+        let patch = old.diff(&new).to_patch();
+        assert_eq!(patch.len(), 3);
+        assert!(old.apply(&patch).is_ok());
+        assert!(old == new);
+
+        // Failing operations are reported with their index
+        let patch = vec![
+            PatchOp::Insert(String::from("word"), 6),
+            PatchOp::Update(String::from("help"), 7),
+            PatchOp::Remove(String::from("hello")),
+        ];
+        assert_eq!(old.apply(&patch), Err((1, TrieError::KeyNotFound)));
+        assert_eq!(old.get("word"), Some(&6));
+        assert_eq!(old.get("hello"), Some(&1));
+        assert_eq!(
+            old.apply(&vec![PatchOp::Remove(String::from("help"))]),
+            Err((0, TrieError::KeyNotFound))
+        );
+    }
+
+    #[test]
+    fn subtrie_reads_relative_keys() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        for (key, value) in [("he", 1), ("hello", 2), ("help", 3), ("world", 4)] {
+            assert!(trie.insert(key, value).is_ok());
+        }
+
+        let subtrie = trie.subtrie("he").unwrap();
+        assert_eq!(subtrie.get("llo"), Some(&2));
+        assert_eq!(subtrie.get("lp"), Some(&3));
+        assert_eq!(subtrie.get(""), Some(&1));
+        assert_eq!(subtrie.get("l"), None);
+        assert_eq!(subtrie.get("world"), None);
+        assert_eq!(subtrie.keys(), vec!["", "llo", "lp"]);
+        assert_eq!(
+            trie.subtrie("hel").unwrap().iter().collect::<Vec<_>>(),
+            vec![(String::from("lo"), &2), (String::from("p"), &3)]
+        );
+
+        assert!(trie.subtrie("x").is_none());
+        assert_eq!(trie.subtrie("").unwrap().keys().len(), 4);
+    }
+
+    #[test]
+    fn with_capacity_preallocates_top_level() {
+        // Create new `Trie`
+        let mut trie = Trie::with_capacity(26);
+        let capacity = trie.raw.top_level_nodes.capacity();
+        assert!(capacity >= 26);
+
+        for key in 'a'..='z' {
+            assert!(trie.insert(&format!("{}bc", key), 1).is_ok());
+        }
+        assert_eq!(trie.raw.top_level_nodes.capacity(), capacity);
+        assert_eq!(trie.get("zbc"), Some(&1));
+        assert_eq!(trie.len(), 26);
+    }
+
+    #[test]
+    fn suffixes_of_prefix() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        for key in ["hello", "help", "world"] {
+            assert!(trie.insert(key, 1).is_ok());
+        }
+
+        assert_eq!(trie.suffixes_of("hel"), vec!["lo", "p"]);
+        assert_eq!(trie.suffixes_of("help"), vec![""]);
+        assert_eq!(trie.suffixes_of(""), vec!["hello", "help", "world"]);
+        assert!(trie.suffixes_of("x").is_empty());
+    }
+
+    #[cfg(feature = "access-tracking")]
+    #[test]
+    fn access_count_counts_lookups() {
+        // Create new `Trie`
+        let mut trie = Trie::new().with_access_tracking(true);
+
+        // Insert values
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("help", 2).is_ok());
+
+        // Look keys up
+        for _ in 0..3 {
+            assert_eq!(trie.get("hello"), Some(&1));
+        }
+        assert!(trie.contains_key("hello"));
+        assert!(trie.contains_key("help"));
+        assert!(!trie.contains_key("hel"));
+
+        assert_eq!(trie.access_count("hello"), Some(4));
+        assert_eq!(trie.access_count("help"), Some(1));
+        assert_eq!(trie.access_count("hel"), None);
+        assert_eq!(trie.access_count("world"), None);
+
+        // Nothing is counted with tracking off
+        let mut trie = Trie::new();
+        assert!(trie.insert("hello", 1).is_ok());
+        assert_eq!(trie.get("hello"), Some(&1));
+        assert_eq!(trie.access_count("hello"), Some(0));
+    }
+
+    #[test]
+    fn probe_reports_key_and_prefix() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("he", 1).is_ok());
+        assert!(trie.insert("hello", 2).is_ok());
+
+        let probe = |is_key, is_prefix| Probe { is_key, is_prefix };
+        assert_eq!(trie.probe("he"), probe(true, true));
+        assert_eq!(trie.probe("hel"), probe(false, true));
+        assert_eq!(trie.probe("hello"), probe(true, false));
+        assert_eq!(trie.probe("help"), probe(false, false));
+        assert_eq!(trie.probe(""), probe(false, true));
+    }
+
+    #[test]
+    fn clear_values_prefix_keeps_nodes() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("user/1", 1).is_ok());
+        assert!(trie.insert("user/12", 2).is_ok());
+        assert!(trie.insert("users", 3).is_ok());
+        assert!(trie.insert("group/1", 4).is_ok());
+        let node_count = trie.node_count();
+
+        assert_eq!(trie.clear_values_prefix("user/"), 2);
+        assert_eq!(trie.get("user/1"), None);
+        assert_eq!(trie.get("user/12"), None);
+        assert_eq!(trie.get("users"), Some(&3));
+        assert_eq!(trie.len(), 2);
+        assert_eq!(trie.node_count(), node_count);
+        assert!(!trie.starts_with("user/"));
+        assert!(trie.starts_with("user"));
+
+        // The keys can be inserted again without new nodes
+        assert!(trie.insert("user/12", 5).is_ok());
+        assert_eq!(trie.get("user/12"), Some(&5));
+        assert_eq!(trie.node_count(), node_count);
+        assert!(trie.starts_with("user/"));
+
+        assert_eq!(trie.clear_values_prefix("none"), 0);
+        assert_eq!(trie.clear_values_prefix(""), 3);
+        assert!(trie.is_empty());
+        assert_eq!(trie.node_count(), node_count);
+    }
+
+    // Checks every node's count of values below it against a fresh count, and `count_prefix`
+    // against the keys
+    fn assert_subtree_counts<T>(trie: &Trie<T>) {
+        let mut stack: Vec<_> = trie.raw.top_level_nodes.values().collect();
+        while let Some(node) = stack.pop() {
+            let below: usize = node
+                .child_nodes
+                .values()
+                .map(|child| child.subtree_value_count)
+                .sum();
+            assert_eq!(node.subtree_value_count, usize::from(node.is_end()) + below);
+            stack.extend(node.child_nodes.values());
+        }
+        for (key, _) in trie.iter() {
+            for (end, _) in key.char_indices().skip(1) {
+                let prefix = &key[..end];
+                assert_eq!(
+                    trie.count_prefix(prefix),
+                    trie.keys_with_prefix(prefix).len()
+                );
+            }
+        }
+        assert_eq!(trie.count_prefix(""), trie.len());
+    }
+
+    #[test]
+    fn count_prefix_survives_churn() {
+        // Create new `Trie`
+        let mut trie = Trie::new().with_policy(DuplicatePolicy::Replace);
+
+        // Keys over a small alphabet, so that they share prefixes and collide
+        let mut seed = 7u64;
+        let mut next_key = move || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let len = 1 + (seed >> 60) as usize % 6;
+            (0..len)
+                .map(|i| {
+                    if (seed >> (40 + i)) & 1 == 0 {
+                        'a'
+                    } else {
+                        'b'
+                    }
+                })
+                .collect::<String>()
+        };
+
+        for round in 0..200 {
+            let key = next_key();
+            match round % 12 {
+                0..=2 => {
+                    let _ = trie.insert(&key, round);
+                }
+                3 => *trie.entry(&key).or_insert(0) += 1,
+                4 => {
+                    trie.swap_value(&key, round);
+                }
+                5 => {
+                    let mut cursor = trie.insert_cursor();
+                    let _ = cursor.insert(&key, round);
+                    let _ = cursor.insert(&(key.clone() + "a"), round);
+                }
+                6 => {
+                    let _ = trie.remove(&key);
+                }
+                7 => {
+                    trie.remove_many(&[&key, &next_key()]);
+                }
+                8 => trie.retain(|_, value| *value % 3 != 0),
+                9 => {
+                    let _ = trie.rename_prefix(&key, &next_key());
+                }
+                10 => {
+                    trie.clear_values_prefix(&key);
+                }
+                _ => {
+                    let _ = trie.remove_and_promote(&key);
+                    trie.remove_prefix(&(key + "bb"));
+                }
+            }
+            assert_subtree_counts(&trie);
+        }
+
+        assert_eq!(trie.count_prefix("c"), 0);
+        trie.drain_prefix("a");
+        assert_eq!(trie.count_prefix("a"), 0);
+        assert_subtree_counts(&trie);
+        trie.clear_values();
+        assert_subtree_counts(&trie);
+    }
+
+    #[test]
+    fn get_at_depth_counts_chars() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("über", 2).is_ok());
+
+        assert_eq!(trie.get_at_depth("hello"), Some((&1, 5)));
+        assert_eq!(trie.get_at_depth("über"), Some((&2, 4)));
+        assert_eq!(trie.get_at_depth("hel"), None);
+        assert_eq!(trie.get_at_depth(""), None);
+
+        // The decomposed key has 5 chars, its composed node is at depth 4
+        #[cfg(feature = "unicode-normalization")]
+        {
+            let mut trie = Trie::new().with_normalization(Normalization::Nfc);
+            assert!(trie.insert("caf\u{e9}", 1).is_ok());
+            assert_eq!(trie.get_at_depth("cafe\u{301}"), Some((&1, 4)));
+        }
+    }
+
+    #[test]
+    fn load_lines_inserts_each_word() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        assert!(trie.insert("hello", ()).is_ok());
+
+        let words = "hello\r\nhelp  \n\n   \nworld\nhelp\n";
+        assert_eq!(trie.load_lines(io::Cursor::new(words)).unwrap(), 2);
+        assert_eq!(trie.keys_with_prefix(""), ["hello", "help", "world"]);
+
+        // Other insert errors fail the load
+        let mut trie = Trie::new().with_max_key_len(4);
+        let err = trie
+            .load_lines(io::Cursor::new("word\nwords\n"))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(trie.contains_key("word"));
+    }
+
+    #[test]
+    fn truncate_keys_merges_onto_ancestors() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("hello", 1).is_ok());
+        assert!(trie.insert("help", 2).is_ok());
+        assert!(trie.insert("he", 3).is_ok());
+
+        // Both collapse onto "hel", keeping the first value
+        assert_eq!(trie.truncate_keys(3), 2);
+        assert_eq!(trie.keys_with_prefix(""), ["he", "hel"]);
+        assert_eq!(trie.get("hel"), Some(&1));
+        assert_eq!(trie.get("he"), Some(&3));
+        assert_eq!(trie.len(), 2);
+        assert_eq!(trie.node_count(), 3);
+        assert_eq!(trie.count_prefix("h"), 2);
+        assert_eq!(trie.truncate_keys(3), 0);
+
+        // `Replace` keeps the last value, including over the one already there
+        let mut trie = Trie::new().with_policy(DuplicatePolicy::Replace);
+        assert!(trie.insert("hel", 1).is_ok());
+        assert!(trie.insert("hello", 2).is_ok());
+        assert!(trie.insert("help", 3).is_ok());
+        assert!(trie.insert("world", 4).is_ok());
+        assert_eq!(trie.truncate_keys(3), 3);
+        assert_eq!(trie.get("hel"), Some(&3));
+        assert_eq!(trie.get("wor"), Some(&4));
+        assert_eq!(trie.len(), 2);
+    }
+
+    #[test]
+    fn ancestors_walk_down_to_key() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("a", 1).is_ok());
+        assert!(trie.insert("abc", 2).is_ok());
+
+        assert_eq!(
+            trie.ancestors("abc"),
+            [
+                ("a".to_owned(), Some(&1)),
+                ("ab".to_owned(), None),
+                ("abc".to_owned(), Some(&2))
+            ]
+        );
+        assert_eq!(trie.ancestors("ab").len(), 2);
+        assert!(trie.ancestors("abcd").is_empty());
+        assert!(trie.ancestors("").is_empty());
+    }
+
+    #[test]
+    fn keys_by_value_ranks_largest_first() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Count words
+        for word in "the cat saw the dog and the bird saw a cat".split(' ') {
+            *trie.entry_or_default(word) += 1;
+        }
+
+        let ranking: Vec<_> = trie
+            .keys_by_value()
+            .into_iter()
+            .map(|(key, count)| (key, *count))
+            .collect();
+        let expected = [
+            ("the", 3),
+            ("cat", 2),
+            ("saw", 2),
+            ("a", 1),
+            ("and", 1),
+            ("bird", 1),
+            ("dog", 1),
+        ];
+        assert_eq!(
+            ranking,
+            expected.map(|(key, count)| (key.to_owned(), count))
+        );
+    }
+
+    #[test]
+    fn insert_if_absent_keeps_existing_value() {
+        // Create new `Trie`
+        let mut trie = Trie::new().with_policy(DuplicatePolicy::Replace);
+
+        assert!(trie.insert_if_absent("hello", 1));
+        assert!(!trie.insert_if_absent("hello", 2));
+        assert!(trie.insert_if_absent("hel", 3));
+        assert!(!trie.insert_if_absent("", 4));
+
+        assert_eq!(trie.get("hello"), Some(&1));
+        assert_eq!(trie.get("hel"), Some(&3));
+        assert_eq!(trie.len(), 2);
+    }
+
+    #[test]
+    fn leaf_keys_skip_interior_keys() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        for key in ["hel", "hello", "help", "world", "a/b", "a/b/c", "a/d"] {
+            assert!(trie.insert(key, ()).is_ok());
+        }
+        assert_eq!(trie.leaf_keys(), ["a/b/c", "a/d", "hello", "help", "world"]);
+
+        // A key whose longer keys only left empty nodes behind is a leaf again
+        trie.clear_values_prefix("hell");
+        trie.clear_values_prefix("help");
+        assert_eq!(trie.leaf_keys(), ["a/b/c", "a/d", "hel", "world"]);
+    }
+
+    #[test]
+    fn try_variants_reject_empty_keys_without_panicking() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        assert_eq!(
+            trie.try_get_or_insert_with("", || 1),
+            Err(TrieError::EmptyKey)
+        );
+        assert_eq!(trie.try_swap_value("", 1), Err(TrieError::EmptyKey));
+        assert!(matches!(trie.try_entry(""), Err(TrieError::EmptyKey)));
+        assert_eq!(trie.try_entry_or_default(""), Err(TrieError::EmptyKey));
+        assert_eq!(trie.try_truncate_keys(0), Err(TrieError::EmptyKey));
+        assert!(trie.is_empty());
+
+        // Unusual but non-empty keys work like any other key
+        let long_key = "ü".repeat(10_000);
+        for key in ["\0", "e\u{301}", "\u{10FFFF}", &long_key] {
+            assert_eq!(trie.try_get_or_insert_with(key, || 1), Ok(&mut 1));
+            assert_eq!(trie.try_swap_value(key, 2), Ok(Some(1)));
+            assert!(matches!(trie.try_entry(key), Ok(Entry::Occupied(_))));
+            *trie.try_entry_or_default(key).unwrap() += 1;
+            assert_eq!(trie.get(key), Some(&3));
+        }
+        assert_eq!(trie.len(), 4);
+        assert_eq!(trie.try_truncate_keys(1), Ok(2));
+        assert_eq!(trie.get("ü"), Some(&3));
+    }
+
+    #[test]
+    fn insert_sorted_dedup_sums_duplicates() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        assert!(trie.insert("zebra", 7).is_ok());
+
+        // Count words
+        let mut pairs: Vec<_> = "the cat and the hat and the bat"
+            .split(' ')
+            .map(|word| (word.to_string(), 1))
+            .collect();
+        assert!(trie
+            .insert_sorted_dedup(&mut pairs, |count, n| *count += n)
+            .is_ok());
+        assert!(pairs.is_empty());
+        assert_eq!(trie.len(), 6);
+        assert_eq!(trie.get("the"), Some(&3));
+        assert_eq!(trie.get("and"), Some(&2));
+        assert_eq!(trie.get("cat"), Some(&1));
+        assert_eq!(trie.get("zebra"), Some(&7));
+        assert_subtree_counts(&trie);
+
+        // A key already stored is rejected, and the pairs after it are handed back
+        let mut pairs = vec![
+            ("dog".to_string(), 1),
+            ("cat".to_string(), 1),
+            ("eel".to_string(), 1),
+            ("dog".to_string(), 1),
+        ];
+        assert_eq!(
+            trie.insert_sorted_dedup(&mut pairs, |count, n| *count += n),
+            Err(("cat".to_string(), TrieError::DuplicateKey))
+        );
+        assert_eq!(pairs, [("dog".to_string(), 2), ("eel".to_string(), 1)]);
+        assert_eq!(trie.get("cat"), Some(&1));
+        assert_eq!(trie.get("dog"), None);
+    }
+
+    #[test]
+    fn resolve_chain_layers_dotted_keys() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("app", "base").is_ok());
+        assert!(trie.insert("app.module", "module").is_ok());
+        assert!(trie.insert("app.module.feature", "feature").is_ok());
+        assert!(trie.insert("app.other", "other").is_ok());
+
+        assert_eq!(
+            trie.resolve_chain("app.module.feature"),
+            [
+                ("app".to_string(), &"base"),
+                ("app.module".to_string(), &"module"),
+                ("app.module.feature".to_string(), &"feature"),
+            ]
+        );
+        // Keys past the stored ones fall back to the chain so far
+        assert_eq!(
+            trie.resolve_chain("app.module.unknown"),
+            [
+                ("app".to_string(), &"base"),
+                ("app.module".to_string(), &"module"),
+            ]
+        );
+        assert_eq!(
+            trie.resolve_chain("app.module.unknown")
+                .last()
+                .map(|(_, value)| *value),
+            trie.ancestor_value("app.module.unknown")
+        );
+        assert!(trie.resolve_chain("ap").is_empty());
+        assert!(trie.resolve_chain("").is_empty());
+    }
+
+    #[test]
+    fn iter_mut_updates_values_by_key() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        for key in ["hel", "hello", "help", "über"] {
+            assert!(trie.insert(key, key.to_string()).is_ok());
+        }
+
+        // Append the key length to every value
+        for (key, value) in trie.iter_mut() {
+            value.push_str(&key.chars().count().to_string());
+        }
+        for (key, value) in &mut trie {
+            if key.starts_with("help") {
+                value.push('!');
+            }
+        }
+
+        let pairs: Vec<_> = trie
+            .iter()
+            .map(|(key, value)| (key, value.as_str()))
+            .collect();
+        assert_eq!(
+            pairs,
+            [
+                ("hel".to_string(), "hel3"),
+                ("hello".to_string(), "hello5"),
+                ("help".to_string(), "help4!"),
+                ("über".to_string(), "über4"),
+            ]
+        );
+    }
+
+    #[test]
+    fn node_view_navigates_from_the_root() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        assert!(trie.insert("hel", 1).is_ok());
+        assert!(trie.insert("hello", 2).is_ok());
+        assert!(trie.insert("help", 3).is_ok());
+        assert!(trie.insert("world", 4).is_ok());
+
+        let root = trie.node_view("").unwrap();
+        assert!(!root.is_endpoint());
+        assert_eq!(root.child_chars(), ['h', 'w']);
+
+        // Step down to "hel" one char at a time
+        let mut node = root.child('h').unwrap();
+        for char in ['e', 'l'] {
+            assert!(!node.is_endpoint());
+            node = node.child(char).unwrap();
+        }
+        assert!(node.is_endpoint());
+        assert_eq!(node.value(), Some(&1));
+        assert_eq!(node.child_chars(), ['l', 'p']);
+        assert_eq!(node.child('p').unwrap().value(), Some(&3));
+        assert!(node.child('x').is_none());
+
+        let node = trie.node_view("hell").unwrap();
+        assert_eq!(node.value(), None);
+        assert_eq!(node.child('o').unwrap().value(), Some(&2));
+        assert!(node.child('o').unwrap().child_chars().is_empty());
+        assert!(trie.node_view("hex").is_none());
+    }
+
+    #[test]
+    fn intern_values_shares_equal_values() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values, each in its own allocation
+        for (key, tag) in [("apple", "fruit"), ("kale", "leaf"), ("pear", "fruit")] {
+            assert!(trie.insert(key, Rc::new(tag.to_string())).is_ok());
+        }
+        assert!(!Rc::ptr_eq(
+            trie.get("apple").unwrap(),
+            trie.get("pear").unwrap()
+        ));
+
+        trie.intern_values();
+        assert!(Rc::ptr_eq(
+            trie.get("apple").unwrap(),
+            trie.get("pear").unwrap()
+        ));
+        assert_eq!(Rc::strong_count(trie.get("apple").unwrap()), 2);
+        assert!(!Rc::ptr_eq(
+            trie.get("apple").unwrap(),
+            trie.get("kale").unwrap()
+        ));
+        assert_eq!(**trie.get("pear").unwrap(), "fruit");
+        assert_eq!(trie.len(), 3);
+    }
+
+    #[test]
+    fn try_for_each_prefix_stops_at_break() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        for (value, key) in ["he", "hello", "help", "helpful", "world"]
+            .iter()
+            .enumerate()
+        {
+            assert!(trie.insert(key, value).is_ok());
+        }
+
+        // Find the first key under "hel" longer than 4 chars
+        let mut visited = Vec::new();
+        let found = trie.try_for_each_prefix("hel", |key, value| {
+            visited.push(key.to_string());
+            if key.len() > 4 {
+                ControlFlow::Break((key.to_string(), *value))
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert_eq!(found, ControlFlow::Break(("hello".to_string(), 1)));
+        assert_eq!(visited, ["hello"]);
+
+        // Nothing under "wor" is longer than 5 chars
+        let found = trie.try_for_each_prefix("wor", |key, _| {
+            if key.len() > 5 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert_eq!(found, ControlFlow::Continue(()));
+    }
+
+    #[test]
+    fn unique_prefixes_abbreviate_commands() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        for key in [
+            "commit",
+            "clone",
+            "config",
+            "checkout",
+            "cherry-pick",
+            "push",
+            "pull",
+        ] {
+            assert!(trie.insert(key, ()).is_ok());
+        }
+        assert!(trie.insert("co", ()).is_ok());
+
+        let prefixes = trie.unique_prefixes();
+        let expected = [
+            ("commit", "com"),
+            ("clone", "cl"),
+            ("config", "con"),
+            ("checkout", "chec"),
+            ("cherry-pick", "cher"),
+            ("push", "pus"),
+            ("pull", "pul"),
+            // Only the whole key tells it apart from "commit" and "config"
+            ("co", "co"),
+        ];
+        assert_eq!(prefixes.len(), expected.len());
+        for (key, prefix) in expected {
+            assert_eq!(prefixes[key], prefix);
+        }
+        assert_eq!(Trie::<()>::new().unique_prefixes(), HashMap::new());
+    }
+
+    #[test]
+    fn any_key_with_prefix_gives_an_example() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        assert_eq!(trie.any_key_with_prefix(""), None);
+
+        // Insert values
+        for key in ["help", "hello", "helium", "world"] {
+            assert!(trie.insert(key, ()).is_ok());
+        }
+        assert_eq!(trie.any_key_with_prefix("he").as_deref(), Some("helium"));
+        assert_eq!(trie.any_key_with_prefix("hell").as_deref(), Some("hello"));
+        assert_eq!(trie.any_key_with_prefix("help").as_deref(), Some("help"));
+        assert_eq!(trie.any_key_with_prefix("").as_deref(), Some("helium"));
+        assert_eq!(trie.any_key_with_prefix("hex"), None);
+
+        // Nodes left without values below them don't count
+        trie.clear_values_prefix("hel");
+        assert_eq!(trie.any_key_with_prefix("he"), None);
+        assert_eq!(trie.any_key_with_prefix("").as_deref(), Some("world"));
+    }
+
+    #[test]
+    fn children_capacity_hint_preallocates_new_nodes() {
+        // Create new `Trie`
+        let mut trie = Trie::new().with_children_capacity_hint(40);
+
+        // Every interior node gets 36 children
+        let chars: Vec<_> = ('a'..='z').chain('0'..='9').collect();
+        let mut keys = Vec::new();
+        for a in &chars {
+            for b in &chars {
+                keys.push(format!("x{}{}", a, b));
+            }
+        }
+        for (i, key) in keys.iter().enumerate() {
+            if i % 2 == 0 {
+                assert!(trie.insert(key, i).is_ok());
+            } else {
+                trie.entry(key).or_insert(i);
+            }
+        }
+        assert_eq!(trie.len(), keys.len());
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(trie.get(key), Some(&i));
+        }
+        assert_subtree_counts(&trie);
+
+        let node = trie.get_node("xa").unwrap();
+        assert_eq!(node.child_nodes.len(), chars.len());
+        assert!(node.child_nodes.capacity() >= 40);
+        let leaf = trie.get_node("xab").unwrap();
+        assert!(leaf.child_nodes.capacity() >= 40);
+
+        // Nodes made by the insert cursor get the hint too
+        let mut cursor = trie.insert_cursor();
+        assert!(cursor.insert("yz", 0).is_ok());
+        drop(cursor);
+        assert!(trie.get_node("y").unwrap().child_nodes.capacity() >= 40);
+        assert!(Trie::<i32>::new().raw.top_level_nodes.capacity() < 40);
+    }
+
+    #[test]
+    fn iter_bfs_yields_shorter_keys_first() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+
+        // Insert values
+        for (value, key) in ["hello", "help", "a", "he", "world", "ab"]
+            .iter()
+            .enumerate()
+        {
+            assert!(trie.insert(key, value).is_ok());
+        }
+
+        let keys: Vec<_> = trie.iter_bfs().into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, ["a", "ab", "he", "help", "hello", "world"]);
+        let dfs_keys: Vec<_> = trie.iter().map(|(key, _)| key).collect();
+        assert_eq!(dfs_keys, ["a", "ab", "he", "hello", "help", "world"]);
+        assert!(keys.windows(2).all(|pair| pair[0].len() <= pair[1].len()));
+        assert_eq!(trie.iter_bfs()[2], ("he".to_string(), &3));
+    }
+
+    #[test]
+    fn compact_summary_groups_shared_prefixes() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        assert_eq!(trie.compact_summary(), "");
+
+        // Insert values
+        for key in ["hello", "hey", "back"] {
+            assert!(trie.insert(key, ()).is_ok());
+        }
+        assert_eq!(trie.compact_summary(), "back,he(llo|y)");
+
+        // Keys that are prefixes of others, and nested branches
+        for key in ["he", "help", "helpful", "b"] {
+            assert!(trie.insert(key, ()).is_ok());
+        }
+        assert_eq!(trie.compact_summary(), "b(|ack),he(|l(lo|p(|ful))|y)");
+
+        // Nodes without values below them are left out
+        trie.clear_values_prefix("hel");
+        assert_eq!(trie.compact_summary(), "b(|ack),he(|y)");
+    }
+
+    #[test]
+    fn sum_and_max_of_values() {
+        // Create new `Trie`
+        let mut trie: Trie<i32> = Trie::new();
+        assert_eq!(trie.sum_values(), 0);
+        assert_eq!(trie.max_value(), None);
+
+        // Insert values, including ones at interior nodes
+        for (key, value) in [("he", 3), ("hello", -2), ("help", 10), ("world", 4)] {
+            assert!(trie.insert(key, value).is_ok());
+        }
+        assert_eq!(trie.sum_values(), 15);
+        assert_eq!(trie.max_value(), Some(&10));
+
+        assert!(trie.remove("help").is_ok());
+        assert_eq!(trie.sum_values(), 5);
+        assert_eq!(trie.max_value(), Some(&4));
+    }
+
+    #[test]
+    fn panicking_default_leaves_counts_unchanged() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        for key in ["a", "ab", "abc", "b", "ba", "bab", "c", "ca", "cab"] {
+            assert!(trie.insert(key, 1).is_ok());
+        }
+
+        // Below an existing key, next to one and under a new first char
+        for key in ["abcd", "aa", "d"] {
+            let result = catch_unwind(AssertUnwindSafe(|| {
+                trie.get_or_insert_with(key, || panic!("no value"));
+            }));
+            assert!(result.is_err());
+        }
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            trie.entry("cb").or_insert_with(|| panic!("no value"));
+        }));
+        assert!(result.is_err());
+
+        assert_eq!(trie.len(), 9);
+        assert_eq!(trie.iter().count(), 9);
+        assert_eq!(trie.count_prefix("a"), 3);
+        assert_eq!(trie.any_key_with_prefix("abc"), Some("abc".to_string()));
+        assert_eq!(trie.any_key_with_prefix("d"), None);
+    }
+
+    #[test]
+    fn get_pair_mut_with_an_empty_key() {
+        // Create new `Trie`
+        let mut trie = Trie::new();
+        assert!(trie.insert("hello", 1).is_ok());
+
+        assert_eq!(trie.get_pair_mut("", "hello"), (None, Some(&mut 1)));
+        assert_eq!(trie.get_pair_mut("hello", ""), (Some(&mut 1), None));
+        assert_eq!(trie.get_pair_mut("", "world"), (None, None));
     }
 }
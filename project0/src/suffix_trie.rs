@@ -0,0 +1,81 @@
+use crate::{error::TrieError, single_threaded_trie::Trie};
+
+/// A `Trie` storing its keys reversed, so it can answer suffix queries
+pub struct SuffixTrie<T> {
+    trie: Trie<T>,
+}
+
+impl<T> Default for SuffixTrie<T> {
+    fn default() -> Self {
+        Self { trie: Trie::new() }
+    }
+}
+
+impl<T> SuffixTrie<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&T> {
+        self.trie.get(&reversed(key))
+    }
+
+    pub fn insert(&mut self, key: &str, value: T) -> Result<(), TrieError> {
+        self.trie.insert(&reversed(key), value)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Result<(), TrieError> {
+        self.trie.remove(&reversed(key))
+    }
+
+    /// Returns whether any stored key ends with `suffix`
+    pub fn ends_with(&self, suffix: &str) -> bool {
+        self.trie.starts_with(&reversed(suffix))
+    }
+
+    /// Returns every stored key ending with `suffix`, ordered by their reversed keys
+    pub fn keys_with_suffix(&self, suffix: &str) -> Vec<String> {
+        self.trie
+            .keys_with_prefix(&reversed(suffix))
+            .iter()
+            .map(|key| reversed(key))
+            .collect()
+    }
+}
+
+fn reversed(key: &str) -> String {
+    key.chars().rev().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_with_suffix() {
+        // Create new `SuffixTrie`
+        let mut trie = SuffixTrie::new();
+
+        // Insert values
+        assert!(trie.insert("quickly", 1).is_ok());
+        assert!(trie.insert("slowly", 2).is_ok());
+        assert!(trie.insert("fling", 3).is_ok());
+        assert!(trie.insert("quick", 4).is_ok());
+
+        // Get values
+        assert_eq!(trie.get("slowly"), Some(&2));
+        assert_eq!(trie.get("ylwols"), None);
+
+        // Query suffixes
+        let mut keys = trie.keys_with_suffix("ly");
+        keys.sort();
+        assert_eq!(keys, vec!["quickly", "slowly"]);
+        assert!(trie.ends_with("ing"));
+        assert!(trie.ends_with("ick"));
+        assert!(!trie.ends_with("ed"));
+
+        // Delete value
+        assert!(trie.remove("slowly").is_ok());
+        assert_eq!(trie.keys_with_suffix("ly"), vec!["quickly"]);
+    }
+}
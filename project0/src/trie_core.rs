@@ -0,0 +1,677 @@
+#[cfg(feature = "access-tracking")]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{
+    collections::{hash_map, HashMap},
+    fmt,
+};
+
+use crate::error::TrieError;
+
+// The children of a node (or the top level nodes), keyed by their char
+pub(crate) type ChildMap<T> = HashMap<char, TrieNode<T>, ChildHasher>;
+
+#[cfg(not(test))]
+pub(crate) type ChildHasher = std::collections::hash_map::RandomState;
+
+// Tests count how often chars are hashed
+#[cfg(test)]
+pub(crate) type ChildHasher = hash_counting::CountingState;
+
+/// What `Trie::insert` does when the key already has a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// Return an error and keep the existing value
+    #[default]
+    Reject,
+    /// Overwrite the existing value
+    Replace,
+    /// Keep the existing value and return `Ok`
+    KeepFirst,
+}
+
+// The node storage and algorithms shared by the single-threaded and concurrent tries.
+//
+// Keys are used exactly as given; normalization and other configuration are up to the wrapper.
+pub(crate) struct RawTrie<T> {
+    pub(crate) top_level_nodes: ChildMap<T>,
+    // Capacity the child maps of newly created nodes start with
+    pub(crate) child_capacity: usize,
+}
+
+impl<T> Default for RawTrie<T> {
+    fn default() -> Self {
+        Self {
+            top_level_nodes: ChildMap::default(),
+            child_capacity: 0,
+        }
+    }
+}
+
+impl<T> RawTrie<T> {
+    pub(crate) fn with_capacity(top_level: usize) -> Self {
+        Self {
+            top_level_nodes: ChildMap::with_capacity_and_hasher(top_level, Default::default()),
+            child_capacity: 0,
+        }
+    }
+
+    pub(crate) fn insert(
+        &mut self,
+        key: &str,
+        value: T,
+        policy: DuplicatePolicy,
+    ) -> Result<(), TrieError> {
+        if key.is_empty() {
+            return Err(TrieError::EmptyKey);
+        }
+        self.path_or_insert(key).insert(value, policy)
+    }
+
+    // Returns the path to the node at `key`, creating it and its ancestors if needed; panics on
+    // empty keys
+    pub(crate) fn path_or_insert(&mut self, key: &str) -> NodePath<'_, T> {
+        let mut key_iter = key.chars();
+        let first_key = key_iter.next().expect("Key can not be empty");
+
+        let child_capacity = self.child_capacity;
+        self.top_level_nodes
+            .entry(first_key)
+            .or_insert_with(|| TrieNode::with_child_capacity(child_capacity))
+            .path_or_insert(key_iter, Vec::new(), child_capacity)
+    }
+
+    // Returns the path to the node at `key`, if there is one
+    pub(crate) fn path_mut(&mut self, key: &str) -> Option<NodePath<'_, T>> {
+        let mut key_iter = key.chars();
+        let mut node = self.top_level_nodes.get_mut(&key_iter.next()?)?;
+        let mut ancestor_counts = Vec::new();
+        for key in key_iter {
+            let TrieNode {
+                child_nodes,
+                subtree_value_count,
+                ..
+            } = node;
+            ancestor_counts.push(subtree_value_count);
+            node = child_nodes.get_mut(&key)?;
+        }
+        Some(NodePath::new(ancestor_counts, node))
+    }
+
+    pub(crate) fn get_node(&self, key: &str) -> Option<&TrieNode<T>> {
+        let mut key_iter = key.chars();
+        let mut node = self.top_level_nodes.get(&key_iter.next()?)?;
+        for key in key_iter {
+            node = node.child_nodes.get(&key)?;
+        }
+        Some(node)
+    }
+
+    pub(crate) fn get_node_mut(&mut self, key: &str) -> Option<&mut TrieNode<T>> {
+        let mut key_iter = key.chars();
+        self.top_level_nodes
+            .get_mut(&key_iter.next()?)?
+            .get_descendant_mut(key_iter)
+    }
+
+    // Whether a node on the way to `key` has no values below it, like the nodes an insert leaves
+    // behind when making its value panics
+    pub(crate) fn has_empty_node_along(&self, key: &str) -> bool {
+        let mut map = &self.top_level_nodes;
+        for key in key.chars() {
+            let Some(node) = map.get(&key) else {
+                return false;
+            };
+            if node.subtree_value_count == 0 {
+                return true;
+            }
+            map = &node.child_nodes;
+        }
+        false
+    }
+
+    // Removes the highest node on the way to `key` that has no values below it, with its
+    // descendants; returns whether there was one
+    pub(crate) fn prune_empty_along(&mut self, key: &str) -> bool {
+        let mut map = &mut self.top_level_nodes;
+        for key in key.chars() {
+            match map.entry(key) {
+                hash_map::Entry::Occupied(entry) if entry.get().subtree_value_count == 0 => {
+                    entry.remove();
+                    return true;
+                }
+                hash_map::Entry::Occupied(entry) => map = &mut entry.into_mut().child_nodes,
+                hash_map::Entry::Vacant(_) => return false,
+            }
+        }
+        false
+    }
+
+    // Takes the value at `key` if `pred` accepts it, then prunes the nodes that are left with
+    // neither a value nor children
+    pub(crate) fn remove_value_if(
+        &mut self,
+        key: &str,
+        pred: impl FnOnce(&T) -> bool,
+    ) -> Option<T> {
+        self.remove_value_and_prune_if(key, pred)
+            .map(|(value, _)| value)
+    }
+
+    // Like `remove_value_if`, but also returns how many nodes were pruned
+    pub(crate) fn remove_value_and_prune_if(
+        &mut self,
+        key: &str,
+        pred: impl FnOnce(&T) -> bool,
+    ) -> Option<(T, usize)> {
+        // ------------------------------------------------
+        // Get node
+        let mut key_iter = key.chars();
+        let mut current_node = self.top_level_nodes.get_mut(&key_iter.next()?)?;
+        let mut ancestor_counts = Vec::new();
+
+        // Depth of the highest node that only exists for the value being removed
+        let mut prune_depth = 0;
+        let mut key_len = 1;
+
+        for (depth, key) in key_iter.enumerate() {
+            if current_node.is_end() || current_node.child_nodes.len() > 1 {
+                prune_depth = depth + 1;
+            }
+            let TrieNode {
+                child_nodes,
+                subtree_value_count,
+                ..
+            } = current_node;
+            ancestor_counts.push(subtree_value_count);
+            current_node = child_nodes.get_mut(&key)?;
+            key_len += 1;
+        }
+
+        // ------------------------------------------------
+        // Removing
+
+        if !pred(current_node.value.as_ref()?) {
+            return None;
+        }
+        let mut path = NodePath::new(ancestor_counts, current_node);
+        let value = path.take_value()?;
+
+        if !path.node.child_nodes.is_empty() {
+            return Some((value, 0));
+        }
+        // Walk down again to the parent of the highest node to prune
+        let mut key_iter = key.chars();
+        let mut map = &mut self.top_level_nodes;
+        for key in key_iter.by_ref().take(prune_depth) {
+            map = &mut map.get_mut(&key).unwrap().child_nodes;
+        }
+        map.remove(&key_iter.next().unwrap());
+
+        Some((value, key_len - prune_depth))
+    }
+
+    // Takes the values at `keys` and prunes once afterwards; returns how many were present
+    pub(crate) fn remove_many<K: AsRef<str>>(
+        &mut self,
+        keys: impl IntoIterator<Item = K>,
+        shrink: bool,
+    ) -> usize {
+        let removed = keys
+            .into_iter()
+            .filter(|key| {
+                self.path_mut(key.as_ref())
+                    .and_then(|mut path| path.take_value())
+                    .is_some()
+            })
+            .count();
+
+        if removed > 0 {
+            prune_nodes(&mut self.top_level_nodes, shrink);
+        }
+        removed
+    }
+
+    // Detaches the subtree rooted at `prefix` (which must not be empty), pruning the ancestors
+    // that only existed for it
+    pub(crate) fn take_subtree(&mut self, prefix: &str) -> Option<TrieNode<T>> {
+        let mut key_iter = prefix.chars();
+        let mut current_node = self.top_level_nodes.get(&key_iter.next()?)?;
+
+        // Depth of the highest node that only exists for the subtree being detached
+        let mut prune_depth = 0;
+
+        for (depth, key) in key_iter.enumerate() {
+            if current_node.is_end() || current_node.child_nodes.len() > 1 {
+                prune_depth = depth + 1;
+            }
+            current_node = current_node.child_nodes.get(&key)?;
+        }
+        let removed = current_node.subtree_value_count;
+
+        let mut key_iter = prefix.chars();
+        let mut map = &mut self.top_level_nodes;
+        for key in key_iter.by_ref().take(prune_depth) {
+            let node = map.get_mut(&key).unwrap();
+            node.subtree_value_count -= removed;
+            map = &mut node.child_nodes;
+        }
+        // The detached nodes above the subtree have no value and a single child
+        let mut node = map.remove(&key_iter.next().unwrap()).unwrap();
+        for key in key_iter {
+            node = node.child_nodes.remove(&key).unwrap();
+        }
+        Some(node)
+    }
+
+    // Whether a key below `node`, placed at `key`, would collide with a stored value
+    pub(crate) fn collides_with(&self, key: &str, node: &TrieNode<T>) -> bool {
+        let Some(existing_node) = self.get_node(key) else {
+            return false;
+        };
+        let mut stack = vec![(node, existing_node)];
+        while let Some((node, existing_node)) = stack.pop() {
+            if node.is_end() && existing_node.is_end() {
+                return true;
+            }
+            for (key, child) in &node.child_nodes {
+                if let Some(existing_child) = existing_node.child_nodes.get(key) {
+                    stack.push((child, existing_child));
+                }
+            }
+        }
+        false
+    }
+
+    // Places the subtree `node` at `key` (which must not be empty), merging it with the nodes
+    // already there. Returns how many values were dropped because both had one: `Replace`
+    // keeps the value from `node`, the other policies the existing one.
+    pub(crate) fn merge_subtree(
+        &mut self,
+        key: &str,
+        node: TrieNode<T>,
+        policy: DuplicatePolicy,
+    ) -> usize {
+        // Every value of `node` is counted first, and the dropped ones are taken off afterwards
+        let mut path = self.path_or_insert(key);
+        path.add_to_ancestors(node.subtree_value_count);
+
+        // Keys of the dropped values, relative to `key`
+        let mut dropped = Vec::new();
+        let mut stack = vec![(path.into_node(), node, String::new())];
+        while let Some((existing_node, mut node, relative_key)) = stack.pop() {
+            // Reuse the whole subtree where nothing is there yet
+            if !existing_node.is_end() && existing_node.child_nodes.is_empty() {
+                *existing_node = node;
+                continue;
+            }
+            existing_node.subtree_value_count += node.subtree_value_count;
+
+            if let Some(value) = node.value.take() {
+                if !existing_node.is_end() {
+                    existing_node.value = Some(value);
+                    #[cfg(feature = "insertion-order")]
+                    {
+                        existing_node.sequence = node.sequence;
+                    }
+                } else {
+                    if policy == DuplicatePolicy::Replace {
+                        existing_node.value = Some(value);
+                    }
+                    dropped.push(relative_key.clone());
+                }
+            }
+
+            let (shared_children, new_children): (Vec<_>, Vec<_>) = node
+                .child_nodes
+                .drain()
+                .partition(|(key, _)| existing_node.child_nodes.contains_key(key));
+            existing_node.child_nodes.extend(new_children);
+            let mut shared_children: ChildMap<T> = shared_children.into_iter().collect();
+            for (key, existing_child) in &mut existing_node.child_nodes {
+                if let Some(child) = shared_children.remove(key) {
+                    stack.push((existing_child, child, format!("{}{}", relative_key, key)));
+                }
+            }
+        }
+
+        for relative_key in &dropped {
+            let mut path = self.path_mut(&format!("{}{}", key, relative_key)).unwrap();
+            path.add_to_counts(-1);
+        }
+        dropped.len()
+    }
+}
+
+// Fails for empty keys, which the panicking methods reject by panicking
+pub(crate) fn check_not_empty(key: &str) -> Result<(), TrieError> {
+    if key.is_empty() {
+        return Err(TrieError::EmptyKey);
+    }
+    Ok(())
+}
+
+// Removes the nodes that don't lead to any value
+pub(crate) fn prune_nodes<T>(map: &mut ChildMap<T>, shrink: bool) {
+    retain_nodes(map, shrink, |_, _| {});
+}
+
+// Calls `f` with the key of every node and the node, parents before their children, then
+// removes the nodes that don't lead to any value.
+//
+// The children of a node are detached from it while they are visited, so this needs no
+// recursion however deep the trie is.
+pub(crate) fn retain_nodes<T>(
+    map: &mut ChildMap<T>,
+    shrink: bool,
+    mut f: impl FnMut(&str, &mut TrieNode<T>),
+) {
+    struct Frame<T> {
+        map: ChildMap<T>,
+        pending_keys: Vec<char>,
+        child_count: usize,
+    }
+
+    impl<T> Frame<T> {
+        fn new(map: ChildMap<T>) -> Self {
+            Self {
+                pending_keys: map.keys().copied().collect(),
+                child_count: map.len(),
+                map,
+            }
+        }
+    }
+
+    let mut key = String::new();
+    let mut stack = vec![Frame::new(std::mem::take(map))];
+    while let Some(frame) = stack.last_mut() {
+        if let Some(child_key) = frame.pending_keys.pop() {
+            let node = frame.map.get_mut(&child_key).unwrap();
+            key.push(child_key);
+            f(&key, node);
+            if node.child_nodes.is_empty() {
+                node.subtree_value_count = usize::from(node.is_end());
+                key.pop();
+            } else {
+                let child_nodes = std::mem::take(&mut node.child_nodes);
+                stack.push(Frame::new(child_nodes));
+            }
+            continue;
+        }
+
+        let mut frame = stack.pop().unwrap();
+        frame
+            .map
+            .retain(|_, node| node.is_end() || !node.child_nodes.is_empty());
+        if shrink {
+            shrink_children(&mut frame.map, frame.child_count);
+        }
+        match stack.last_mut() {
+            Some(parent) => {
+                let child_key = key.pop().unwrap();
+                let node = parent.map.get_mut(&child_key).unwrap();
+                node.subtree_value_count = usize::from(node.is_end())
+                    + frame
+                        .map
+                        .values()
+                        .map(|child| child.subtree_value_count)
+                        .sum::<usize>();
+                node.child_nodes = frame.map;
+            }
+            None => *map = frame.map,
+        }
+    }
+}
+
+// Releases the spare capacity of `map` if it has at most half of its former `child_count`
+pub(crate) fn shrink_children<T>(map: &mut ChildMap<T>, child_count: usize) {
+    if map.len() <= child_count / 2 {
+        map.shrink_to_fit();
+    }
+}
+
+pub(crate) struct TrieNode<T> {
+    pub(crate) value: Option<T>,
+    pub(crate) child_nodes: ChildMap<T>,
+    // Number of values stored in this node and below it
+    pub(crate) subtree_value_count: usize,
+    // When `value` was last set while the node had none
+    #[cfg(feature = "insertion-order")]
+    pub(crate) sequence: u64,
+    // How often a lookup resolved to `value`
+    #[cfg(feature = "access-tracking")]
+    pub(crate) accesses: AtomicU64,
+}
+
+// Drops the descendants one at a time, as the recursive default would overflow the stack for
+// very deep tries
+impl<T> Drop for TrieNode<T> {
+    fn drop(&mut self) {
+        let mut stack: Vec<_> = self.child_nodes.drain().map(|(_, node)| node).collect();
+        while let Some(mut node) = stack.pop() {
+            stack.extend(node.child_nodes.drain().map(|(_, node)| node));
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for TrieNode<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TrieNode")
+            .field("value", &self.value)
+            .field("child_nodes", &SortedNodes(&self.child_nodes))
+            .finish()
+    }
+}
+
+// Formats child nodes ordered by `char` so the output does not depend on `HashMap` order
+pub(crate) struct SortedNodes<'a, T>(pub(crate) &'a ChildMap<T>);
+
+impl<T: fmt::Debug> fmt::Debug for SortedNodes<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut nodes: Vec<_> = self.0.iter().collect();
+        nodes.sort_unstable_by_key(|(key, _)| **key);
+        f.debug_map().entries(nodes).finish()
+    }
+}
+
+impl<T> TrieNode<T> {
+    pub(crate) fn is_end(&self) -> bool {
+        self.value.is_some()
+    }
+
+    // Whether this node or one of its descendants has a value
+    pub(crate) fn has_value(&self) -> bool {
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            if node.is_end() {
+                return true;
+            }
+            stack.extend(node.child_nodes.values());
+        }
+        false
+    }
+
+    pub(crate) fn with_child_capacity(capacity: usize) -> Self {
+        TrieNode {
+            value: None,
+            child_nodes: ChildMap::with_capacity_and_hasher(capacity, Default::default()),
+            subtree_value_count: 0,
+            #[cfg(feature = "insertion-order")]
+            sequence: 0,
+            #[cfg(feature = "access-tracking")]
+            accesses: AtomicU64::new(0),
+        }
+    }
+
+    // Counts a lookup that resolved to this node, if it holds a value
+    #[cfg(feature = "access-tracking")]
+    pub(crate) fn record_access(&self) {
+        if self.is_end() {
+            self.accesses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[cfg(feature = "access-tracking")]
+    pub(crate) fn access_count(&self) -> Option<u64> {
+        self.is_end().then(|| self.accesses.load(Ordering::Relaxed))
+    }
+
+    // Follows `key_iter` down from this node, creating the missing nodes, and returns the path
+    // to where it ends. `ancestor_counts` holds the counts of the nodes above this one, and new
+    // nodes get child maps with room for `child_capacity` children.
+    pub(crate) fn path_or_insert<'a, I: Iterator<Item = char>>(
+        &'a mut self,
+        key_iter: I,
+        mut ancestor_counts: Vec<&'a mut usize>,
+        child_capacity: usize,
+    ) -> NodePath<'a, T> {
+        let mut current_trie = self;
+
+        for key in key_iter {
+            let TrieNode {
+                child_nodes,
+                subtree_value_count,
+                ..
+            } = current_trie;
+            ancestor_counts.push(subtree_value_count);
+            current_trie = child_nodes
+                .entry(key)
+                .or_insert_with(|| TrieNode::with_child_capacity(child_capacity));
+        }
+        NodePath::new(ancestor_counts, current_trie)
+    }
+
+    pub(crate) fn insert(&mut self, value: T, policy: DuplicatePolicy) -> Result<(), TrieError> {
+        match (self.is_end(), policy) {
+            (true, DuplicatePolicy::Reject) => Err(TrieError::DuplicateKey),
+            (true, DuplicatePolicy::KeepFirst) => Ok(()),
+            _ => {
+                self.value = Some(value);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn get_node_mut(&mut self, key: &char) -> Option<&mut TrieNode<T>> {
+        self.child_nodes.get_mut(key)
+    }
+
+    // Follows `key_iter` down from this node
+    pub(crate) fn get_descendant_mut<I: Iterator<Item = char>>(
+        &mut self,
+        key_iter: I,
+    ) -> Option<&mut TrieNode<T>> {
+        let mut current_trie = self;
+        for key in key_iter {
+            current_trie = current_trie.get_node_mut(&key)?;
+        }
+        Some(current_trie)
+    }
+}
+
+// A node reached from the top level, with the value counts of the nodes above it, so that
+// setting or taking its value keeps every count on the way right
+pub(crate) struct NodePath<'a, T> {
+    ancestor_counts: Vec<&'a mut usize>,
+    pub(crate) node: &'a mut TrieNode<T>,
+}
+
+impl<'a, T> NodePath<'a, T> {
+    pub(crate) fn new(ancestor_counts: Vec<&'a mut usize>, node: &'a mut TrieNode<T>) -> Self {
+        Self {
+            ancestor_counts,
+            node,
+        }
+    }
+
+    pub(crate) fn into_node(self) -> &'a mut TrieNode<T> {
+        self.node
+    }
+
+    // Adds `delta` to the count of the node and of its ancestors
+    pub(crate) fn add_to_counts(&mut self, delta: isize) {
+        for count in &mut self.ancestor_counts {
+            **count = count.checked_add_signed(delta).unwrap();
+        }
+        let count = &mut self.node.subtree_value_count;
+        *count = count.checked_add_signed(delta).unwrap();
+    }
+
+    pub(crate) fn add_to_ancestors(&mut self, added: usize) {
+        for count in &mut self.ancestor_counts {
+            **count += added;
+        }
+    }
+
+    pub(crate) fn insert(&mut self, value: T, policy: DuplicatePolicy) -> Result<(), TrieError> {
+        let is_new = !self.node.is_end();
+        self.node.insert(value, policy)?;
+        if is_new {
+            self.add_to_counts(1);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn replace_value(&mut self, value: T) -> Option<T> {
+        let old_value = self.node.value.replace(value);
+        if old_value.is_none() {
+            self.add_to_counts(1);
+        }
+        old_value
+    }
+
+    pub(crate) fn get_or_insert_with(mut self, f: impl FnOnce() -> T) -> &'a mut T {
+        if !self.node.is_end() {
+            self.node.value = Some(f());
+            self.add_to_counts(1);
+        }
+        self.node.value.as_mut().unwrap()
+    }
+
+    pub(crate) fn take_value(&mut self) -> Option<T> {
+        let value = self.node.value.take()?;
+        self.add_to_counts(-1);
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod hash_counting {
+    use std::{
+        cell::Cell,
+        collections::hash_map::{DefaultHasher, RandomState},
+        hash::{BuildHasher, Hasher},
+    };
+
+    thread_local! {
+        static HASH_COUNT: Cell<usize> = const { Cell::new(0) };
+    }
+
+    // Returns the number of hashes computed by `ChildMap`s on this thread so far
+    pub(crate) fn hash_count() -> usize {
+        HASH_COUNT.with(Cell::get)
+    }
+
+    #[derive(Default, Clone)]
+    pub(crate) struct CountingState(RandomState);
+
+    impl BuildHasher for CountingState {
+        type Hasher = CountingHasher;
+
+        fn build_hasher(&self) -> CountingHasher {
+            CountingHasher(self.0.build_hasher())
+        }
+    }
+
+    pub(crate) struct CountingHasher(DefaultHasher);
+
+    impl Hasher for CountingHasher {
+        fn write(&mut self, bytes: &[u8]) {
+            self.0.write(bytes);
+        }
+
+        fn finish(&self) -> u64 {
+            HASH_COUNT.with(|count| count.set(count.get() + 1));
+            self.0.finish()
+        }
+    }
+}